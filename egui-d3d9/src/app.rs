@@ -1,20 +1,125 @@
-use clipboard::{windows_clipboard::WindowsClipboardContext, ClipboardProvider};
-use egui::{epaint::Primitive, Context};
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    collections::HashSet,
+    rc::Rc,
+    sync::Once,
+    time::{Duration, Instant},
+};
+
+use egui::{
+    epaint::{ClippedShape, Primitive, TessellationOptions},
+    Context, Event, FontData, FontDefinitions, FontFamily, FontTweak, FullOutput, Key, Mesh,
+    Modifiers, Order, Pos2, RawInput, Rect, TextureHandle, TextureId, TextureOptions,
+    TexturesDelta, Vec2, Visuals,
+};
+use windows::Foundation::Numerics::Matrix4x4;
 use windows::Win32::{
-    Foundation::{HWND, LPARAM, RECT, WPARAM},
-    Graphics::Direct3D9::{IDirect3DDevice9, D3DPT_TRIANGLELIST, D3DVIEWPORT9},
-    UI::WindowsAndMessaging::GetClientRect,
+    Foundation::{E_INVALIDARG, HWND, LPARAM, RECT, WPARAM},
+    Graphics::Direct3D9::{
+        IDirect3DDevice9, IDirect3DIndexBuffer9, IDirect3DSurface9, IDirect3DTexture9,
+        IDirect3DVertexBuffer9, D3DBACKBUFFER_TYPE, D3DCAPS9, D3DERR_NOTAVAILABLE, D3DFORMAT,
+        D3DGETDATA_FLUSH, D3DISSUE_END, D3DPT_TRIANGLELIST, D3DQUERYTYPE_EVENT,
+        D3DRS_SCISSORTESTENABLE, D3DSAMP_SRGBTEXTURE, D3DSURFACE_DESC, D3DTRANSFORMSTATETYPE,
+        D3DTS_PROJECTION, D3DTS_VIEW, D3DVIEWPORT9,
+    },
+    UI::WindowsAndMessaging::{GetClientRect, IsWindow},
 };
 
 use crate::{
-    inputman::InputManager,
-    mesh::{Buffers, GpuVertex, MeshDescriptor},
-    state::DxState,
+    clipman::Clipboard,
+    error::EguiDx9Error,
+    inputman::{InputManager, InputResult, InputSnapshot},
+    layers::{mark_orders, split_by_order, ORDERS},
+    mesh::{clip_mesh, draw_ranges, is_transparent, Buffers, GpuVertex, MeshDescriptor},
+    state::{
+        context_transform, BackBufferTarget, DxState, Orientation, RenderOptions, RenderStage,
+    },
     texman::TextureManager,
 };
 
-pub struct EguiDx9<T> {
-    ui_fn: Box<dyn FnMut(&Context, &mut T) + 'static>,
+/// bounds for `set_zoom_factor`, past these the UI is either unreadable or doesn't fit anywhere.
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 4.;
+
+/// what a call to `present` ended up doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PresentStatus {
+    /// a new frame was tessellated, uploaded and drawn.
+    Drawn,
+    /// `present_layer` drew more layers of the frame an earlier call ran, the UI didn't run.
+    DrawnRemainingLayers,
+    /// reactive mode had nothing new, the geometry of the last frame was drawn again.
+    SkippedReactive,
+    /// nothing happened since the last frame and `set_skip_idle_frames` is on,
+    /// the geometry of the last frame was drawn again without running the UI.
+    SkippedIdle,
+    /// the frame came too soon after the last one for `set_max_fps`, the geometry of the last frame was drawn again.
+    SkippedThrottled,
+    /// the window was destroyed, nothing was done. see `set_hwnd`.
+    SkippedNoWindow,
+    /// the window has no client area (e.g. it's minimized), nothing was done.
+    SkippedHidden,
+    /// the application is in the background and `set_pause_when_inactive` is on, nothing was done.
+    SkippedInactive,
+    /// `present` was called from within `present`, the nested call did nothing.
+    SkippedReentrant,
+    /// the device is lost or not reset yet, nothing was done.
+    SkippedDeviceLost,
+    /// egui produced no shapes, or more than our buffers can hold (see `last_error`), nothing was drawn.
+    EmptyFrame,
+}
+
+/// what `present` did, and what the UI closure returned if it ran this frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PresentOutput<R = ()> {
+    pub status: PresentStatus,
+    /// `None` if the UI closure didn't run, e.g. because the frame was skipped.
+    pub value: Option<R>,
+}
+
+impl<R> PresentOutput<R> {
+    fn skipped(status: PresentStatus) -> Self {
+        Self {
+            status,
+            value: None,
+        }
+    }
+}
+
+/// another egui context we draw, see `EguiDx9::add_context`.
+struct ExtraContext {
+    ctx: Context,
+    ui_fn: Box<dyn FnMut(&Context) + 'static>,
+    /// replaces our screen-space projection, `None` keeps it.
+    projection: Option<Matrix4x4>,
+    /// egui's texture ids are per context, so every context needs its own.
+    tex_man: TextureManager,
+}
+
+/// one draw call's worth of geometry from `EguiDx9::tessellate_frame`.
+pub struct DrawMesh {
+    /// egui's vertices and indices, positions are in points.
+    pub mesh: Mesh,
+    /// the scissor rect, in back buffer pixels.
+    pub clip: RECT,
+    /// `None` if we don't have the texture, e.g. between `pre_reset` and `post_reset`.
+    pub texture: Option<IDirect3DTexture9>,
+}
+
+/// a frame's worth of geometry, for drawing it yourself. see `EguiDx9::tessellate_frame`.
+pub struct FrameGeometry<R = ()> {
+    pub meshes: Vec<DrawMesh>,
+    /// back buffer pixels per point, to scale the positions by.
+    pub pixels_per_point: f32,
+    /// what the UI closure returned, `None` if it didn't run.
+    pub value: Option<R>,
+}
+
+/// `R` is whatever the UI closure returns, handed back from `present`. most UIs return nothing.
+pub struct EguiDx9<T, R = ()> {
+    ui_fn: Box<dyn FnMut(&Context, &mut T) -> R + 'static>,
+    post_render: Option<Box<dyn FnMut(&IDirect3DDevice9) + 'static>>,
     ui_state: T,
     hwnd: HWND,
     reactive: bool,
@@ -22,14 +127,46 @@ pub struct EguiDx9<T> {
     // get it? tEx-man? tax-man? no?
     tex_man: TextureManager,
     ctx: Context,
+    fonts: FontDefinitions,
+    follow_system_theme: bool,
+    render_opts: RenderOptions,
     buffers: Buffers,
+    min_buffer_size: (usize, usize),
     prims: Vec<MeshDescriptor>,
-    last_idx_capacity: usize,
-    last_vtx_capacity: usize,
     should_reset: bool,
+    stale_buffers: bool,
+    pause_when_inactive: bool,
+    frame_interval: Option<Duration>,
+    last_frame: Option<Instant>,
+    animating: bool,
+    pixels_per_point: Option<f32>,
+    presenting: Rc<Cell<bool>>,
+    max_draw_prims: usize,
+    max_draw_vertices: u32,
+    last_error: Option<EguiDx9Error>,
+    skip_idle_frames: bool,
+    debug_overlay: bool,
+    next_repaint: Option<Instant>,
+    last_size: Option<(f32, f32)>,
+    /// the last client size `GetClientRect` gave us, for when it fails.
+    client_size: Option<(f32, f32)>,
+    zoom_factor: f32,
+    initial_scale: f32,
+    record_input: bool,
+    last_input: Option<RawInput>,
+    viewport: D3DVIEWPORT9,
+    drawn_orders: Vec<Order>,
+    screen_size: Option<(f32, f32)>,
+    contexts: Vec<ExtraContext>,
+    /// the texture `capture_backbuffer_to_texture` writes to, kept so every capture reuses it.
+    capture: Option<TextureHandle>,
+    /// size, pointer scale and whether a repaint was due, between `begin_frame` and `end_frame`.
+    open_frame: Option<(f32, f32, Vec2, bool)>,
+    /// what `present_to_surface` draws into, instead of asking the device for the back buffer.
+    target_surface: Option<IDirect3DSurface9>,
 }
 
-impl<T> EguiDx9<T> {
+impl<T, R> EguiDx9<T, R> {
     ///
     /// initialize the backend.
     ///
@@ -42,7 +179,7 @@ impl<T> EguiDx9<T> {
     pub fn init(
         dev: &IDirect3DDevice9,
         hwnd: HWND,
-        ui_fn: impl FnMut(&Context, &mut T) + 'static,
+        ui_fn: impl FnMut(&Context, &mut T) -> R + 'static,
         ui_state: T,
         reactive: bool,
     ) -> Self {
@@ -50,196 +187,2247 @@ impl<T> EguiDx9<T> {
             panic!("invalid hwnd specified in egui init");
         }
 
+        let (max_draw_prims, max_draw_vertices) = Self::get_draw_limits(dev);
+
         Self {
             ui_fn: Box::new(ui_fn),
+            post_render: None,
             ui_state,
             hwnd,
             reactive,
-            tex_man: TextureManager::new(),
+            tex_man: TextureManager::new(dev),
             input_man: InputManager::new(hwnd),
             ctx: Context::default(),
+            fonts: FontDefinitions::default(),
+            follow_system_theme: false,
+            render_opts: RenderOptions::default(),
             buffers: Buffers::create_buffers(dev, 16384, 16384),
+            min_buffer_size: (16384, 16384),
             prims: Vec::new(),
-            last_idx_capacity: 0,
-            last_vtx_capacity: 0,
             should_reset: false,
+            stale_buffers: false,
+            pause_when_inactive: false,
+            frame_interval: None,
+            last_frame: None,
+            animating: false,
+            pixels_per_point: None,
+            presenting: Rc::default(),
+            max_draw_prims,
+            max_draw_vertices,
+            last_error: None,
+            skip_idle_frames: false,
+            debug_overlay: false,
+            next_repaint: None,
+            last_size: None,
+            client_size: None,
+            zoom_factor: 1.,
+            initial_scale: 1.,
+            record_input: false,
+            last_input: None,
+            viewport: D3DVIEWPORT9::default(),
+            drawn_orders: Vec::new(),
+            screen_size: None,
+            contexts: Vec::new(),
+            capture: None,
+            open_frame: None,
+            target_surface: None,
         }
     }
 
     pub fn pre_reset(&mut self) {
         self.buffers.delete_buffers();
         self.tex_man.deallocate_textures();
+        self.contexts
+            .iter_mut()
+            .for_each(|extra| extra.tex_man.deallocate_textures());
 
         self.should_reset = true;
     }
 
-    pub fn present(&mut self, dev: &IDirect3DDevice9) {
-        if unsafe { dev.TestCooperativeLevel() }.is_err() {
-            return;
+    ///
+    /// recreate our device resources right after a successful `Reset`.
+    ///
+    /// call this from your `Reset` hook once the original returned successfully,
+    /// so the very next frame is drawn with valid buffers and textures.
+    /// if you don't, `present` will do it lazily on the next frame instead.
+    ///
+    pub fn post_reset(&mut self, dev: &IDirect3DDevice9) {
+        let (vertices, indices) = reset_buffer_size(&self.prims, self.min_buffer_size);
+        self.buffers = Buffers::create_buffers(dev, vertices, indices);
+        self.tex_man.reallocate_textures(dev);
+        self.contexts
+            .iter_mut()
+            .for_each(|extra| extra.tex_man.reallocate_textures(dev));
+
+        self.should_reset = false;
+        // the new buffers are empty, refill them even if egui doesn't want to repaint.
+        self.stale_buffers = true;
+    }
+
+    pub fn present(&mut self, dev: &IDirect3DDevice9) -> PresentOutput<R> {
+        self.present_layer(dev, |_| true)
+    }
+
+    ///
+    /// `present`, but only draws the egui `Order`s `layers` picks. for splitting the overlay between hooks,
+    /// e.g. `Order::Background` before the game draws its own HUD, and everything else after it.
+    ///
+    /// the UI only runs in the first call of a frame, later ones draw the rest of what it painted.
+    /// asking for an `Order` that was already drawn starts a new frame, which is why `present` always does.
+    ///
+    pub fn present_layer(
+        &mut self,
+        dev: &IDirect3DDevice9,
+        layers: impl Fn(Order) -> bool,
+    ) -> PresentOutput<R> {
+        // a hook firing recursively would otherwise have us relock buffers we're still writing to.
+        let Some(_guard) = PresentGuard::enter(&self.presenting) else {
+            static WARNED: Once = Once::new();
+            WARNED.call_once(|| {
+                warn!("present called while already presenting, ignoring the nested call")
+            });
+            return PresentOutput::skipped(PresentStatus::SkippedReentrant);
+        };
+
+        let layers: Vec<Order> = ORDERS.into_iter().filter(|order| layers(*order)).collect();
+
+        let continues_frame = !self.drawn_orders.is_empty()
+            && !layers.iter().any(|order| self.drawn_orders.contains(order))
+            && !self.should_reset
+            && !self.stale_buffers
+            && unsafe { dev.TestCooperativeLevel() }.is_ok();
+
+        if continues_frame {
+            self.draw_layers(dev, &layers);
+            return PresentOutput::skipped(PresentStatus::DrawnRemainingLayers);
         }
 
-        if self.should_reset {
-            self.buffers = Buffers::create_buffers(dev, 16384, 16384);
-            self.tex_man.reallocate_textures(dev);
+        self.present_frame(dev, &layers)
+    }
+
+    ///
+    /// `present`, but sized to `hwnd` for this frame, e.g. the `hDestWindowOverride` passed to `Present`.
+    ///
+    /// a null `hwnd` (what `Present` gets unless the game overrides it) falls back to ours,
+    /// as do all later calls to `present`. input is still only read from what you feed `wnd_proc`,
+    /// so hook the window the mouse is actually over.
+    ///
+    pub fn present_for_window(&mut self, dev: &IDirect3DDevice9, hwnd: HWND) -> PresentOutput<R> {
+        if hwnd.0 == 0 {
+            return self.present(dev);
         }
 
-        let mut output = self.ctx.run(self.input_man.collect_input(), |ctx| {
-            // safe. present will never run in parallel.
-            (self.ui_fn)(ctx, &mut self.ui_state)
-        });
+        let hwnd = std::mem::replace(&mut self.hwnd, hwnd);
+        let output = self.present(dev);
+        self.hwnd = hwnd;
 
-        if self.should_reset {
-            output.repaint_after = std::time::Duration::ZERO;
+        output
+    }
+
+    ///
+    /// `present`, but drawn into `surface`, e.g. the back buffer your hook already holds.
+    ///
+    /// this skips fetching the back buffer set with `set_back_buffer`, and takes precedence over it
+    /// for this call only. the UI is sized and scaled for `surface`, which has to cover the viewport
+    /// (the client area at the current pixels per point), or nothing is drawn and `last_error` says why.
+    /// with `RenderStage::EndScene`, the game's own render target is still bound again afterwards.
+    ///
+    pub fn present_to_surface(
+        &mut self,
+        dev: &IDirect3DDevice9,
+        surface: &IDirect3DSurface9,
+    ) -> PresentOutput<R> {
+        // a hook firing while we draw may call this again, that call mustn't take our surface away.
+        let previous = self.target_surface.replace(surface.clone());
+        let output = self.present(dev);
+        self.target_surface = previous;
+
+        output
+    }
 
-            self.should_reset = false;
+    ///
+    /// run the UI and tessellate it like `present` does, but hand the geometry back instead of drawing it.
+    ///
+    /// for custom renderers and render graphs that schedule their own draws. nothing is drawn,
+    /// and our vertex and index buffers are left alone. textures are still created and updated
+    /// on `dev` as egui asks, so the handles in `DrawMesh::texture` are ready to use. ones egui
+    /// frees this frame are released on our side right away, the handles keep them alive until you drop them.
+    /// frame pacing (`set_max_fps`, reactive mode) doesn't apply, every call runs the UI.
+    /// only our own UI is in there, contexts from `add_context` don't run.
+    ///
+    pub fn tessellate_frame(&mut self, dev: &IDirect3DDevice9) -> FrameGeometry<R> {
+        let mut geometry = FrameGeometry {
+            meshes: Vec::new(),
+            pixels_per_point: self.render_opts.pixels_per_point,
+            value: None,
+        };
+
+        if window_gone(self.hwnd) {
+            return geometry;
         }
 
+        if self.should_reset {
+            self.post_reset(dev);
+        }
+
+        let Some((w, h, ppp, pointer_scale)) = self.frame_size(dev) else {
+            return geometry;
+        };
+
+        let (output, value) = self.run_ui(dev, w, h, ppp, pointer_scale);
+
+        let painted = split_by_order(output.shapes);
+        geometry.meshes = tessellate(&self.ctx, painted, &self.render_opts, w, h)
+            .into_iter()
+            .map(|(desc, mesh)| DrawMesh {
+                clip: desc.clip,
+                texture: self.tex_man.get_by_id(mesh.texture_id).cloned(),
+                mesh,
+            })
+            .collect();
+        geometry.pixels_per_point = ppp;
+        geometry.value = value;
+
         if !output.textures_delta.is_empty() {
-            self.tex_man.process_set_deltas(dev, &output.textures_delta);
+            self.tex_man.process_free_deltas(&output.textures_delta);
         }
 
-        if !output.platform_output.copied_text.is_empty() {
-            let _ = WindowsClipboardContext.set_contents(output.platform_output.copied_text);
+        geometry
+    }
+
+    ///
+    /// start a frame to build the UI against directly, instead of through the closure passed to `init`.
+    ///
+    /// returns `None` if there is nothing to draw this frame (the device is lost, the window is gone
+    /// or hidden, or the app is paused by `set_pause_when_inactive`). skip your UI and `end_frame` then.
+    /// otherwise call `end_frame` with the same device once you're done, before the next
+    /// `begin_frame`, `present` or `tessellate_frame`. the `init` closure isn't called,
+    /// and frame pacing (`set_max_fps`, `set_skip_idle_frames`) doesn't apply.
+    ///
+    pub fn begin_frame(&mut self, dev: &IDirect3DDevice9) -> Option<&Context> {
+        if self.open_frame.is_some() {
+            panic!("egui begin_frame called again before end_frame");
         }
 
-        if output.shapes.is_empty() {
-            // early return, don't forget to free textures
-            if !output.textures_delta.is_empty() {
-                self.tex_man.process_free_deltas(&output.textures_delta);
-            }
-            return;
+        if unsafe { dev.TestCooperativeLevel() }.is_err()
+            || (self.pause_when_inactive && !self.input_man.is_app_active())
+            || window_gone(self.hwnd)
+        {
+            return None;
         }
 
-        // we only need to update the buffers if we are actually changing something
-        if output.repaint_after.is_zero() || !self.reactive {
-            let mut vertices: Vec<GpuVertex> = Vec::with_capacity(self.last_vtx_capacity + 512);
-            let mut indices: Vec<u32> = Vec::with_capacity(self.last_idx_capacity + 512);
+        if self.should_reset {
+            self.post_reset(dev);
+        }
 
-            self.prims = self
-                .ctx
-                .tessellate(output.shapes)
-                .into_iter()
-                .filter_map(|prim| {
-                    if let Primitive::Mesh(mesh) = prim.primitive {
-                        // most definitely not the rusty way to do this.
-                        // it's ugly, but its efficient.
-                        if let Some((gpumesh, verts, idxs)) =
-                            MeshDescriptor::from_mesh(mesh, prim.clip_rect)
-                        {
-                            vertices.extend_from_slice(verts.as_slice());
-                            indices.extend_from_slice(idxs.as_slice());
-
-                            Some(gpumesh)
-                        } else {
-                            None
-                        }
-                    } else {
-                        panic!("paint callbacks not supported")
-                    }
-                })
-                .collect();
+        let (w, h, ppp, pointer_scale) = self.frame_size(dev)?;
+
+        // beginning the frame replaces the deadline, so check it first.
+        let repaint_due = self.is_repaint_due();
+
+        self.begin_ui(w, h, ppp, pointer_scale);
+        self.open_frame = Some((w, h, pointer_scale, repaint_due));
+
+        Some(&self.ctx)
+    }
+
+    ///
+    /// finish a frame started with `begin_frame`, and draw it like `present` would.
+    ///
+    /// panics if there is no frame to finish, e.g. because `begin_frame` returned `None`.
+    ///
+    pub fn end_frame(&mut self, dev: &IDirect3DDevice9) -> PresentStatus {
+        let Some((w, h, pointer_scale, repaint_due)) = self.open_frame.take() else {
+            panic!("egui end_frame called without begin_frame");
+        };
+
+        self.show_debug_overlay();
+
+        let mut output = self.ctx.end_frame();
+        self.finish_ui(dev, &mut output, w, h, pointer_scale);
+
+        // a hook firing while we draw calls `present`, which has to back off like it does for itself.
+        let _guard = PresentGuard::enter(&self.presenting);
 
-            self.last_vtx_capacity = vertices.len();
-            self.last_idx_capacity = indices.len();
+        self.drawn_orders.clear();
+        self.draw_output(dev, output, w, h, repaint_due, &ORDERS)
+    }
+
+    fn present_frame(&mut self, dev: &IDirect3DDevice9, layers: &[Order]) -> PresentOutput<R> {
+        self.drawn_orders.clear();
 
-            self.buffers.update_vertex_buffer(dev, &vertices);
-            self.buffers.update_index_buffer(dev, &indices);
+        if unsafe { dev.TestCooperativeLevel() }.is_err() {
+            return PresentOutput::skipped(PresentStatus::SkippedDeviceLost);
         }
 
-        // back up our state so we don't mess with the game and the game doesn't mess with us.
-        // i actually had the idea to use BeginStateBlock and co. to "cache" the state we set every frame,
-        // and just re-applying it everytime. just setting this manually takes around 50 microseconds on my machine.
-        let _state = DxState::setup(dev, self.get_viewport());
+        if self.pause_when_inactive && !self.input_man.is_app_active() {
+            return PresentOutput::skipped(PresentStatus::SkippedInactive);
+        }
 
-        unsafe {
-            expect!(
-                dev.SetStreamSource(
-                    0,
-                    expect!(self.buffers.vtx.as_ref(), "unable to get vertex buffer"),
-                    0,
-                    std::mem::size_of::<GpuVertex>() as _
-                ),
-                "unable to set vertex stream source"
-            );
+        if window_gone(self.hwnd) {
+            return PresentOutput::skipped(PresentStatus::SkippedNoWindow);
+        }
 
-            expect!(
-                dev.SetIndices(expect!(
-                    self.buffers.idx.as_ref(),
-                    "unable to get index buffer"
-                ),),
-                "unable to set index buffer"
-            );
+        if self.should_reset {
+            self.post_reset(dev);
         }
 
-        let mut our_vtx_idx: usize = 0;
-        let mut our_idx_idx: usize = 0;
+        let Some((w, h, ppp, pointer_scale)) = self.frame_size(dev) else {
+            return PresentOutput::skipped(PresentStatus::SkippedHidden);
+        };
 
-        self.prims.iter().for_each(|mesh: &MeshDescriptor| unsafe {
-            expect!(dev.SetScissorRect(&mesh.clip), "unable to set scissor rect");
+        // freshly recreated buffers are empty, so never throttle before refilling them.
+        if !self.stale_buffers && self.is_throttled() {
+            self.draw(dev, w, h, layers);
+            return PresentOutput::skipped(PresentStatus::SkippedThrottled);
+        }
+
+        if self.skip_idle_frames && self.reactive && !self.stale_buffers && self.is_idle(w, h) {
+            self.draw(dev, w, h, layers);
+            return PresentOutput::skipped(PresentStatus::SkippedIdle);
+        }
 
-            let texture = self.tex_man.get_by_id(mesh.texture_id);
+        // running the UI replaces the deadline, so check it first.
+        let repaint_due = self.is_repaint_due();
 
-            expect!(dev.SetTexture(0, texture), "unable to set texture");
+        let (output, value) = self.run_ui(dev, w, h, ppp, pointer_scale);
 
-            expect!(
-                dev.DrawIndexedPrimitive(
-                    D3DPT_TRIANGLELIST,
-                    our_vtx_idx as _,
-                    0,
-                    mesh.vertices as _,
-                    our_idx_idx as _,
-                    (mesh.indices / 3usize) as _
-                ),
-                "unable to draw indexed prims"
-            );
+        PresentOutput {
+            status: self.draw_output(dev, output, w, h, repaint_due, layers),
+            value,
+        }
+    }
+
+    ///
+    /// replace all fonts, including ones added through `add_font`.
+    ///
+    /// the definitions are kept on this instance. to keep them when recreating it,
+    /// pass `fonts()` from the old one to the new one's `set_fonts`.
+    ///
+    pub fn set_fonts(&mut self, fonts: FontDefinitions) {
+        self.fonts = fonts;
+        self.ctx.set_fonts(self.fonts.clone());
+    }
+
+    ///
+    /// the fonts set through `set_fonts` and `add_font`, egui's defaults if there were none.
+    ///
+    pub fn fonts(&self) -> &FontDefinitions {
+        &self.fonts
+    }
+
+    ///
+    /// add a font and make it the first choice for `families`.
+    ///
+    /// replaces any font already added under `name`. missing glyphs fall through to the
+    /// fonts after it, see `add_fallback_font`. the atlas is rebuilt at the start of the next frame,
+    /// call `warm_up` afterwards to do it right away.
+    /// this replaces fonts set through `ctx.set_fonts` in your UI closure.
+    ///
+    pub fn add_font(
+        &mut self,
+        name: &str,
+        bytes: impl Into<Cow<'static, [u8]>>,
+        families: &[FontFamily],
+        tweak: Option<FontTweak>,
+    ) {
+        self.insert_font(name, bytes.into(), families, tweak, true);
+    }
 
-            our_vtx_idx += mesh.vertices;
-            our_idx_idx += mesh.indices;
+    ///
+    /// add a font that's only used for glyphs none of the other fonts in `families` have,
+    /// e.g. CJK or emoji.
+    ///
+    /// fallbacks are tried in the order they were added.
+    ///
+    pub fn add_fallback_font(
+        &mut self,
+        name: &str,
+        bytes: impl Into<Cow<'static, [u8]>>,
+        families: &[FontFamily],
+        tweak: Option<FontTweak>,
+    ) {
+        self.insert_font(name, bytes.into(), families, tweak, false);
+    }
+
+    ///
+    /// replace egui's visuals, e.g. to change colors or rounding.
+    ///
+    /// with `set_follow_system_theme` on, these are replaced again when the OS theme changes.
+    ///
+    pub fn set_visuals(&mut self, visuals: Visuals) {
+        self.ctx.set_visuals(visuals);
+        // reactive mode would otherwise keep showing the old colors until something happens.
+        self.next_repaint = Some(Instant::now());
+    }
+
+    ///
+    /// switch between egui's default dark and light visuals.
+    ///
+    pub fn set_dark_mode(&mut self, dark: bool) {
+        self.set_visuals(if dark {
+            Visuals::dark()
+        } else {
+            Visuals::light()
         });
+    }
 
-        if !output.textures_delta.is_empty() {
-            self.tex_man.process_free_deltas(&output.textures_delta);
+    ///
+    /// follow the OS light/dark setting, applied right away and whenever it changes.
+    ///
+    /// off by default. changes are picked up from `WM_SETTINGCHANGE`, so this needs `wnd_proc`.
+    /// visuals you set yourself are replaced on the next change.
+    ///
+    pub fn set_follow_system_theme(&mut self, follow: bool) {
+        self.follow_system_theme = follow;
+
+        if follow {
+            self.apply_system_theme();
         }
     }
 
-    #[inline]
-    pub fn wnd_proc(&mut self, umsg: u32, wparam: WPARAM, lparam: LPARAM) {
-        // safe. we only write here, and only read elsewhere.
-        self.input_man.process(umsg, wparam.0, lparam.0);
+    ///
+    /// whether the OS is set to dark mode, `None` on versions without one.
+    ///
+    /// cached, and read again when `wnd_proc` sees the setting change or every few seconds.
+    ///
+    pub fn system_dark_mode(&self) -> Option<bool> {
+        self.input_man.system_dark_mode()
     }
-}
 
-impl<T> EguiDx9<T> {
-    fn get_screen_size(&self) -> (f32, f32) {
-        let mut rect = RECT::default();
+    ///
+    /// have egui build its font atlas and upload it right away, without drawing anything.
+    ///
+    /// the first frame otherwise does this on its own, which causes a visible hitch.
+    /// call it during a loading screen, after init or changing fonts.
+    /// input isn't consumed, but windows may take an extra frame to settle their size afterwards.
+    ///
+    pub fn warm_up(&mut self, dev: &IDirect3DDevice9) {
+        // nothing can be uploaded to a lost device, the first frame after the reset does it instead.
+        if unsafe { dev.TestCooperativeLevel() }.is_err() {
+            return;
+        }
+
+        if self.should_reset {
+            self.post_reset(dev);
+        }
+
+        let (w, h) = self.screen_size.unwrap_or_else(|| self.get_screen_size());
+        let scale = self.ui_scale();
+        let (w, h) = (w / scale, h / scale);
+
+        let ppp = if w > 0. {
+            self.pixels_per_point
+                .map(|ppp| ppp * scale)
+                .unwrap_or_else(|| self.back_buffer_scale(dev, w))
+        } else {
+            self.render_opts.pixels_per_point
+        };
+
+        let (w, h) = self.render_opts.orientation.logical_size(w, h);
+
+        let output = self.ctx.run(
+            RawInput {
+                screen_rect: Some(Rect::from_min_size(Pos2::ZERO, Vec2::new(w, h))),
+                pixels_per_point: Some(ppp),
+                ..Default::default()
+            },
+            |_| {},
+        );
+
+        self.tex_man.process_set_deltas(dev, &output.textures_delta);
+        self.tex_man.process_free_deltas(&output.textures_delta);
+    }
+
+    ///
+    /// submit everything we've drawn to the gpu right away, instead of whenever the driver gets to it.
+    ///
+    /// `present` already puts the overlay onto the target back buffer before returning,
+    /// so hooking `Present` and calling it before the original needs nothing else.
+    /// if you hook somewhere else (e.g. `EndScene`, which can run several times a frame),
+    /// call this after `present` so the overlay isn't a frame late or missing.
+    /// this doesn't wait for the gpu to finish.
+    ///
+    /// flushing goes through an event query. devices without those do nothing here,
+    /// the driver submits on its own schedule there.
+    ///
+    pub fn flush(&self, dev: &IDirect3DDevice9) -> Result<(), EguiDx9Error> {
         unsafe {
-            expect!(
-                GetClientRect(self.hwnd, &mut rect),
-                "Failed to GetClientRect()"
-            );
+            let query = match dev.CreateQuery(D3DQUERYTYPE_EVENT) {
+                Ok(query) => query,
+                Err(err) if err.code() == D3DERR_NOTAVAILABLE => return Ok(()),
+                Err(err) => return Err(EguiDx9Error::new("unable to create event query", err)),
+            };
+
+            query
+                .Issue(D3DISSUE_END)
+                .map_err(|e| EguiDx9Error::new("unable to issue event query", e))?;
+
+            // S_FALSE just means the gpu isn't done yet, which we don't care about.
+            let _ = query.GetData(std::ptr::null_mut(), 0, D3DGETDATA_FLUSH);
         }
-        (
-            (rect.right - rect.left) as f32,
-            (rect.bottom - rect.top) as f32,
-        )
+
+        Ok(())
     }
 
-    fn get_viewport(&self) -> D3DVIEWPORT9 {
-        let (w, h) = self.get_screen_size();
-        D3DVIEWPORT9 {
-            X: 0,
-            Y: 0,
-            Width: w as _,
-            Height: h as _,
-            MinZ: 0.,
-            MaxZ: 1.,
+    ///
+    /// point the backend at a new window, for games that recreate theirs (e.g. on resolution change).
+    ///
+    /// this only updates our handle. if you hooked the old window's `WndProc`,
+    /// you have to install that hook on the new window yourself.
+    ///
+    pub fn set_hwnd(&mut self, hwnd: HWND) {
+        if hwnd.0 == 0 {
+            panic!("invalid hwnd specified in egui set_hwnd");
         }
+
+        self.hwnd = hwnd;
+        self.input_man.set_hwnd(hwnd);
     }
-}
 
-impl<T> Drop for EguiDx9<T> {
-    fn drop(&mut self) {
-        self.buffers.delete_buffers();
-        self.tex_man.deallocate_textures();
+    ///
+    /// how many vertices and indices our buffers hold at the very least. 16384 each by default.
+    ///
+    /// they grow by doubling from there. if you know your UI is large,
+    /// raising this skips the first few reallocations when it shows up.
+    /// a larger minimum applies the next time the buffers are filled, a smaller one after a `Reset`,
+    /// which recreates them at whatever the last frame needed.
+    ///
+    pub fn set_min_buffer_size(&mut self, vertices: usize, indices: usize) {
+        self.min_buffer_size = (vertices.max(1), indices.max(1));
+    }
+
+    ///
+    /// set the options used when tessellating shapes into meshes.
+    ///
+    /// mostly useful to tune `feathering`/`feathering_size_in_pixels`
+    /// for render-scaled or high-dpi targets. defaults to egui's defaults.
+    ///
+    pub fn set_tessellation_options(&mut self, options: TessellationOptions) {
+        self.ctx
+            .options_mut(|opts| opts.tessellation_options = options);
+    }
+
+    pub fn tessellation_options(&self) -> TessellationOptions {
+        self.ctx.options(|opts| opts.tessellation_options)
+    }
+
+    ///
+    /// give egui a fixed screen size instead of the window's client size, e.g. always 1920x1080
+    /// for recording at a set resolution. `None` (the default) follows the window.
+    ///
+    /// the overlay is stretched from that size onto the whole back buffer,
+    /// so it looks the same no matter how large the window is. `pixels_per_point` follows suit,
+    /// unless overridden. mouse positions are scaled by `size / client size` to match,
+    /// so clicks still land on what's under the cursor.
+    ///
+    pub fn set_screen_size(&mut self, size: Option<(f32, f32)>) {
+        if size.is_some_and(|(w, h)| w <= 0. || h <= 0.) {
+            panic!("invalid screen size specified in egui set_screen_size");
+        }
+
+        self.screen_size = size;
+    }
+
+    ///
+    /// override egui's `pixels_per_point`. `None` (the default) derives it every frame
+    /// from the back buffer size relative to the window's client size.
+    ///
+    /// egui points always map to client pixels (before `set_zoom_factor`), so input is unaffected.
+    /// this only changes how many back buffer pixels each point covers, and so how sharp text is.
+    ///
+    pub fn set_pixels_per_point(&mut self, pixels_per_point: Option<f32>) {
+        if pixels_per_point.is_some_and(|ppp| ppp <= 0.) {
+            panic!("invalid pixels_per_point specified in egui set_pixels_per_point");
+        }
+
+        self.pixels_per_point = pixels_per_point;
+    }
+
+    ///
+    /// scale the whole UI, e.g. for touch screens or an in-game "ui scale" slider. 1.0 is the default.
+    ///
+    /// this is clamped to `0.25..=4.0`. every point covers `zoom` client pixels, and it multiplies
+    /// `pixels_per_point` (derived or overridden), so text stays sharp instead of being stretched.
+    /// egui's own `Event::Zoom` from Ctrl+wheel is unrelated, it's only read by widgets that zoom
+    /// their own content, like plots.
+    ///
+    pub fn set_zoom_factor(&mut self, zoom: f32) {
+        if !zoom.is_finite() || zoom <= 0. {
+            panic!("invalid zoom factor specified in egui set_zoom_factor");
+        }
+
+        self.zoom_factor = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+
+    ///
+    /// the baseline UI scale, e.g. to pin it at 1.25 across monitors. 1.0 is the default.
+    ///
+    /// set it right after `init`, before the first frame. it's kept across device resets.
+    /// unlike `set_zoom_factor` it isn't clamped, and the two multiply: zooming to 2.0 with
+    /// a baseline of 1.25 draws at 2.5. Ctrl+wheel changes neither, see `set_zoom_factor`.
+    ///
+    pub fn set_initial_scale(&mut self, scale: f32) {
+        if !scale.is_finite() || scale <= 0. {
+            panic!("invalid scale specified in egui set_initial_scale");
+        }
+
+        self.initial_scale = scale;
+    }
+
+    ///
+    /// multiply the current zoom factor by `factor`, see `set_zoom_factor`.
+    ///
+    pub fn zoom_by(&mut self, factor: f32) {
+        if !factor.is_finite() || factor <= 0. {
+            panic!("invalid zoom factor specified in egui zoom_by");
+        }
+
+        self.zoom_factor = (self.zoom_factor * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+    }
+
+    ///
+    /// the current zoom factor, see `set_zoom_factor`.
+    ///
+    pub fn zoom_factor(&self) -> f32 {
+        self.zoom_factor
+    }
+
+    ///
+    /// rotate the overlay for rotated/portrait displays.
+    ///
+    /// rendering and mouse input are both rotated, so hit-testing keeps matching what's drawn.
+    ///
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.render_opts.orientation = orientation;
+        self.input_man.set_orientation(orientation);
+    }
+
+    ///
+    /// mirror the overlay vertically.
+    ///
+    /// for hosts whose coordinate system makes egui render upside down.
+    /// mouse input is flipped to match.
+    ///
+    pub fn set_flip_y(&mut self, flip_y: bool) {
+        self.render_opts.flip_y = flip_y;
+        self.input_man.set_flip_y(flip_y);
+    }
+
+    ///
+    /// render with `D3DRS_SRGBWRITEENABLE` on, for hosts whose back buffer expects linear output.
+    ///
+    /// d3d9 surface formats don't say whether they're sRGB, so this has to be set by hand.
+    /// when enabled, vertex colors are converted to linear and textures are sampled with
+    /// `D3DSAMP_SRGBTEXTURE`, letting the hardware re-encode on write.
+    ///
+    /// egui's colors are premultiplied in gamma space, and our blend (ONE, INVSRCALPHA) relies on that.
+    /// linearizing a premultiplied color isn't exactly the same as premultiplying a linear one,
+    /// and older hardware blends in gamma space even with sRGB writes on, so translucent
+    /// edges may look slightly different than with this off.
+    ///
+    pub fn set_srgb_framebuffer(&mut self, srgb: bool) {
+        if self.render_opts.srgb_framebuffer != srgb {
+            self.render_opts.srgb_framebuffer = srgb;
+            // cached geometry was converted for the old mode.
+            self.stale_buffers = true;
+        }
+    }
+
+    ///
+    /// replace where copy, cut and paste go. defaults to the OS clipboard.
+    ///
+    /// use `NullClipboard` to keep clipboard contents inside the overlay.
+    ///
+    pub fn set_clipboard(&mut self, clipboard: impl Clipboard + 'static) {
+        self.input_man.set_clipboard(Box::new(clipboard));
+    }
+
+    ///
+    /// choose which back buffer the overlay is drawn into.
+    ///
+    /// defaults to swap chain 0, back buffer 0, `D3DBACKBUFFER_TYPE_MONO`.
+    /// for stereo rendering, use `D3DBACKBUFFER_TYPE_LEFT`/`RIGHT` to target an eye.
+    ///
+    pub fn set_back_buffer(
+        &mut self,
+        dev: &IDirect3DDevice9,
+        swap_chain: u32,
+        index: u32,
+        kind: D3DBACKBUFFER_TYPE,
+    ) {
+        if swap_chain >= unsafe { dev.GetNumberOfSwapChains() } {
+            panic!("invalid swap chain specified in egui set_back_buffer");
+        }
+
+        self.render_opts.back_buffer = BackBufferTarget {
+            swap_chain,
+            index,
+            kind,
+        };
+    }
+
+    ///
+    /// tell us where you call `present` from. defaults to `RenderStage::Present`.
+    ///
+    /// from `Present`, the game's scene is over, so we wrap our draws in a scene of our own
+    /// and leave the back buffer bound afterwards, as the game will present it next anyway.
+    /// from `EndScene`, we draw inside the game's scene, and put back the render target
+    /// the game had bound, which may be an offscreen one it isn't done with.
+    ///
+    pub fn set_render_stage(&mut self, stage: RenderStage) {
+        self.render_opts.stage = stage;
+    }
+
+    ///
+    /// toggle the classic d3d9 half-pixel offset in the projection. on by default.
+    ///
+    /// some drivers end up shifting by half a texel with it, which makes text blurry.
+    /// turn it off if that's the case for you.
+    ///
+    pub fn set_half_pixel_offset(&mut self, enabled: bool) {
+        self.render_opts.half_pixel_offset = enabled;
+    }
+
+    ///
+    /// unbind the game's depth-stencil surface while drawing. off by default.
+    ///
+    /// egui never depth-tests either way, as we draw with `D3DRS_ZENABLE` and `D3DRS_STENCILENABLE` off.
+    /// but with it left bound, d3d9 still requires it to be at least as large as our render target,
+    /// and some drivers misbehave with it anyway. turn this on if the overlay flickers or goes missing.
+    /// the game's surface is bound again once we're done.
+    ///
+    pub fn set_detach_depth_stencil(&mut self, detach: bool) {
+        self.render_opts.detach_depth_stencil = detach;
+    }
+
+    ///
+    /// clip egui's shapes to their clip rects. on by default.
+    ///
+    /// this is for debugging only: turn it off to see whether something that's missing
+    /// is being clipped away. everything draws unclipped then, scroll areas included.
+    ///
+    pub fn set_scissor_enabled(&mut self, enabled: bool) {
+        self.render_opts.scissor = enabled;
+        // cpu clipping is baked into the cached geometry.
+        self.stale_buffers = true;
+    }
+
+    ///
+    /// clip egui's shapes on the cpu instead of with scissor rects. off by default.
+    ///
+    /// for hosts where changing the scissor rect per draw is slow or misbehaves: the scissor test
+    /// is left off while we draw, and triangles crossing a clip rect are split on the cpu instead.
+    /// that costs some cpu time and a few extra vertices when scrolling, and produces the same image.
+    ///
+    pub fn set_cpu_clipping(&mut self, enabled: bool) {
+        self.render_opts.cpu_clip = enabled;
+        // the cached geometry was clipped (or not) the other way.
+        self.stale_buffers = true;
+    }
+
+    ///
+    /// draw another egui `Context` after ours, e.g. world-space annotations next to a screen-space menu.
+    /// returns its index for `set_context_projection`. contexts are drawn in the order they were added.
+    ///
+    /// `projection` replaces our screen-space projection for it, e.g. with the game's view * projection.
+    /// egui's vertices are in points, with z at 0. `None` draws it in screen space like ours.
+    /// clip rects are in screen space, so they're ignored under a projection of yours.
+    ///
+    /// it runs whenever our UI does and gets the same screen size and time, but no input.
+    /// its textures are kept apart from ours, in `D3DPOOL_DEFAULT` without a texture creator.
+    ///
+    pub fn add_context(
+        &mut self,
+        dev: &IDirect3DDevice9,
+        ctx: Context,
+        ui_fn: impl FnMut(&Context) + 'static,
+        projection: Option<Matrix4x4>,
+    ) -> usize {
+        self.contexts.push(ExtraContext {
+            ctx,
+            ui_fn: Box::new(ui_fn),
+            projection,
+            tex_man: TextureManager::new(dev),
+        });
+        // its meshes are only in the buffers from the next frame that runs the UI.
+        self.stale_buffers = true;
+
+        self.contexts.len() - 1
+    }
+
+    ///
+    /// change the projection of a context from `add_context`, e.g. every frame as the game's camera moves.
+    ///
+    pub fn set_context_projection(&mut self, index: usize, projection: Option<Matrix4x4>) {
+        if let Some(extra) = self.contexts.get_mut(index) {
+            extra.projection = projection;
+        }
+    }
+
+    ///
+    /// show frame time, draw calls, vertices and texture memory in the top right corner. off by default.
+    ///
+    /// it's drawn by egui on top of your UI, from what the previous frame drew.
+    ///
+    pub fn set_debug_overlay(&mut self, enabled: bool) {
+        self.debug_overlay = enabled;
+    }
+
+    ///
+    /// skip meshes that are entirely transparent, e.g. windows fading in or out. off by default.
+    ///
+    /// saves a draw call and the overdraw for each, but every vertex has to be checked first,
+    /// so only turn it on for UIs that fade a lot.
+    ///
+    pub fn set_cull_transparent(&mut self, enabled: bool) {
+        self.render_opts.cull_transparent = enabled;
+        // the cached geometry was culled (or not) the other way.
+        self.stale_buffers = true;
+    }
+
+    ///
+    /// draw with your own world, view and projection matrices instead of our screen-space ones,
+    /// e.g. for a panel anchored somewhere in the game world. `None` (the default) goes back to screen space.
+    ///
+    /// contexts from `add_context` with a projection of their own keep it, the others follow this.
+    ///
+    /// these follow the fixed-function pipeline's conventions: row vectors, transformed as
+    /// `v * world * view * proj`. egui's vertices are in points, with x going right, y going down
+    /// and z at 0, so `world` has to place that plane. d3d9 itself has no handedness, so `view`
+    /// and `proj` should be the game's own (see `IDirect3DDevice9::GetTransform`), which are
+    /// left-handed in most d3d9 games. if the panel shows up mirrored, flip y in `world`.
+    ///
+    /// clip rects are turned off while this is set, and orientation, `set_flip_y` and the half
+    /// pixel offset don't apply. input still maps to screen space, so the panel isn't clickable yet.
+    ///
+    pub fn set_transform_override(&mut self, transform: Option<(Matrix4x4, Matrix4x4, Matrix4x4)>) {
+        self.render_opts.transform = transform;
+    }
+
+    ///
+    /// run `post_render` every time we've drawn, right after the game's render state is restored.
+    ///
+    /// e.g. to draw something of your own on top of egui, or to take a timing sample.
+    /// it runs for every draw, including frames that only redraw the cached geometry,
+    /// and also when drawing failed (see `last_error`).
+    ///
+    pub fn set_post_render(&mut self, post_render: impl FnMut(&IDirect3DDevice9) + 'static) {
+        self.post_render = Some(Box::new(post_render));
+    }
+
+    ///
+    /// stop calling the function set with `set_post_render`.
+    ///
+    pub fn clear_post_render(&mut self) {
+        self.post_render = None;
+    }
+
+    ///
+    /// turn Ctrl+C/X/V into copy, cut and paste. on by default.
+    ///
+    /// even when on, they're only taken while an egui text field has keyboard focus,
+    /// so the game keeps its own shortcuts otherwise.
+    ///
+    pub fn set_clipboard_shortcuts(&mut self, enabled: bool) {
+        self.input_man.set_intercept_clipboard(enabled);
+    }
+
+    ///
+    /// move egui's pointer by raw mouse deltas (`WM_INPUT`) instead of the cursor position.
+    /// off by default.
+    ///
+    /// for games that lock the cursor to the center of the window while they have the mouse,
+    /// which leaves `WM_MOUSEMOVE` useless. turn it on while your overlay is open, and off again
+    /// when the game takes the mouse back. the pointer starts in the middle of the window and
+    /// stays inside it. the game usually hides the cursor too, so you may want to draw your own
+    /// at `ctx.pointer_hover_pos()`.
+    ///
+    /// if the game doesn't use raw input itself, this registers for it on our window.
+    /// either way `WM_INPUT` has to reach `wnd_proc`.
+    ///
+    pub fn set_raw_mouse(&mut self, enabled: bool) {
+        // the pointer starts in the middle, which needs a size before the first frame has one.
+        if self.client_size.is_none() {
+            self.get_screen_size();
+        }
+
+        self.input_man.set_raw_mouse(enabled);
+    }
+
+    ///
+    /// only take mouse input inside `region`, in egui points. `None` (the default) takes it everywhere.
+    ///
+    /// for overlays with a small always-on widget: mouse messages outside the region aren't given
+    /// to egui, and `wnd_proc` returns `InputResult::Unknown` for them so you pass them on to the game.
+    /// releasing a button outside still ends a drag that started inside, the game sees that one too.
+    /// keyboard input is unaffected, use `Context::wants_keyboard_input` to decide where it goes.
+    ///
+    pub fn set_input_region(&mut self, region: Option<Rect>) {
+        self.input_man.set_input_region(region);
+    }
+
+    ///
+    /// give egui the time the first input of a frame arrived at, instead of the time the frame started.
+    /// off by default.
+    ///
+    /// input is always buffered until the next `present`, so it reaches egui up to a frame late,
+    /// in the order it arrived. egui's events carry no timestamps of their own, so all of a frame's
+    /// input shares one time. with this on, that's when the first of it came in, which makes timing
+    /// sensitive UIs (rhythm games, double-click speed) measure from the input rather than the render.
+    /// frames without input still use the current time, so animations are unaffected otherwise.
+    ///
+    pub fn set_time_from_input(&mut self, enabled: bool) {
+        self.input_man.set_time_from_input(enabled);
+    }
+
+    ///
+    /// skip all work in `present` while the user has switched to another application.
+    /// off by default.
+    ///
+    pub fn set_pause_when_inactive(&mut self, pause: bool) {
+        self.pause_when_inactive = pause;
+    }
+
+    ///
+    /// cap how often egui runs and re-tessellates, in frames per second. `None` (the default) means no cap.
+    ///
+    /// frames that come in too early redraw the last frame's geometry as-is, so the overlay doesn't flicker,
+    /// but skip running the UI and uploading new buffers. input and `request_repaint` calls aren't lost,
+    /// they're picked up by the next frame that isn't throttled. this applies on top of reactive mode.
+    ///
+    pub fn set_max_fps(&mut self, fps: Option<u32>) {
+        if fps == Some(0) {
+            panic!("invalid fps specified in egui set_max_fps");
+        }
+
+        self.frame_interval = fps.map(|fps| Duration::from_secs(1) / fps);
+    }
+
+    ///
+    /// serialize egui's memory (window positions, collapsed states, ...), e.g. before dropping us.
+    ///
+    /// hand the result to `load_memory` on a fresh instance, so recreating the overlay doesn't reset it.
+    ///
+    #[cfg(feature = "persistence")]
+    pub fn save_memory(&self) -> Vec<u8> {
+        expect!(
+            self.ctx.memory(ron::to_string),
+            "unable to serialize egui memory"
+        )
+        .into_bytes()
+    }
+
+    ///
+    /// restore egui's memory from what `save_memory` returned.
+    /// fails if `memory` is corrupt or from an incompatible egui version, leaving ours untouched.
+    ///
+    #[cfg(feature = "persistence")]
+    pub fn load_memory(&mut self, memory: &[u8]) -> Result<(), ron::error::SpannedError> {
+        let memory: egui::Memory = ron::de::from_bytes(memory)?;
+
+        self.ctx.memory_mut(|m| *m = memory);
+
+        Ok(())
+    }
+
+    ///
+    /// the most recent d3d failure we recovered from by skipping (part of) a frame, if any.
+    ///
+    /// most failures still panic. the ones recorded here are those we could shrug off,
+    /// so this is meant for logging and diagnostics rather than reacting to every frame.
+    ///
+    pub fn last_error(&self) -> Option<&EguiDx9Error> {
+        self.last_error.as_ref()
+    }
+
+    ///
+    /// keep a copy of the input we give egui each frame, for `debug_last_input`. off by default.
+    ///
+    /// that's a clone of every frame's events, so only turn it on while looking into input issues.
+    ///
+    pub fn set_record_input(&mut self, enabled: bool) {
+        self.record_input = enabled;
+
+        if !enabled {
+            self.last_input = None;
+        }
+    }
+
+    ///
+    /// exactly what we gave egui in the last frame that ran the UI: events, modifiers, screen rect and time.
+    /// attach this to input bug reports. `None` unless `set_record_input` is on.
+    ///
+    pub fn debug_last_input(&self) -> Option<&RawInput> {
+        self.last_input.as_ref()
+    }
+
+    ///
+    /// whether egui wants to be repainted right away, e.g. because of an ongoing animation.
+    ///
+    /// this reflects the state after the last `present` that actually ran the UI.
+    /// keep presenting every frame while this is true, and feel free to throttle while it's not.
+    ///
+    pub fn is_animating(&self) -> bool {
+        self.animating
+    }
+
+    ///
+    /// when egui asked to be repainted next, e.g. to show a tooltip after a delay. `None` means
+    /// only on input. as of the last `present` that ran the UI.
+    ///
+    /// in reactive mode, keep presenting until then at least, or what egui wanted to show won't be drawn.
+    /// this is in the past while `is_animating`.
+    ///
+    pub fn next_repaint(&self) -> Option<Instant> {
+        self.next_repaint
+    }
+
+    ///
+    /// create egui's textures yourself, e.g. with a shared handle for capture software.
+    ///
+    /// `create` gets the size and the pixels in `texture_format`, row by row, and has to return a texture
+    /// that already holds them. we keep updating it with `UpdateSurface`/`UpdateTexture`,
+    /// so it has to be in that format, in `D3DPOOL_DEFAULT`, with a single level of exactly that size. it's also recreated through `create` after a `Reset`.
+    /// textures that already exist are left alone.
+    ///
+    pub fn set_texture_creator(
+        &mut self,
+        create: impl FnMut(&IDirect3DDevice9, [usize; 2], &[u32]) -> IDirect3DTexture9 + 'static,
+    ) {
+        self.tex_man.set_creator(Some(Box::new(create)));
+    }
+
+    ///
+    /// go back to creating textures ourselves, see `set_texture_creator`.
+    ///
+    pub fn clear_texture_creator(&mut self) {
+        self.tex_man.set_creator(None);
+    }
+
+    ///
+    /// create egui's textures in `D3DPOOL_MANAGED` instead of `D3DPOOL_DEFAULT`. off by default.
+    ///
+    /// d3d9 keeps a system memory copy of those and restores them after a `Reset` itself,
+    /// so we don't keep one of our own, which saves RAM with large textures.
+    /// in exchange, whole-texture updates are written in full instead of only where they changed.
+    /// `IDirect3DDevice9Ex` doesn't support the managed pool, so leave this off there.
+    /// it's ignored while a texture creator is set, and textures that already exist are left alone.
+    ///
+    pub fn set_managed_textures(&mut self, managed: bool) {
+        self.tex_man.set_managed(managed);
+    }
+
+    ///
+    /// downscale images larger than `max` pixels on either side when they're uploaded,
+    /// instead of failing to create a texture the device can't hold. `None` (the default) turns it off.
+    ///
+    /// images shrink by a whole factor with a box filter. egui's uvs are normalized,
+    /// so they still map the same, just blurrier. the font atlas is never downscaled,
+    /// and textures that already exist are left alone until egui replaces them.
+    ///
+    pub fn set_max_texture_dimension(&mut self, max: Option<u32>) {
+        if max == Some(0) {
+            panic!("invalid texture dimension specified in egui set_max_texture_dimension");
+        }
+
+        self.tex_man.set_max_dimension(max.map(|max| max as _));
+    }
+
+    ///
+    /// the format we create egui's textures in, picked from what the device supports at init.
+    ///
+    /// this is `D3DFMT_A8R8G8B8` (pixels are `0xAARRGGBB`) unless the device can't do it,
+    /// then `D3DFMT_A8B8G8R8` (`0xAABBGGRR`).
+    ///
+    pub fn texture_format(&self) -> D3DFORMAT {
+        self.tex_man.format()
+    }
+
+    ///
+    /// how many textures we're holding on to, font atlas included.
+    ///
+    pub fn texture_count(&self) -> usize {
+        self.tex_man.count()
+    }
+
+    ///
+    /// drop every texture egui doesn't know about anymore, returning how many there were.
+    ///
+    /// egui frees textures through the frame output, so this should always return 0.
+    /// if it doesn't, something skipped freeing them, and this at least gets the memory back.
+    ///
+    pub fn collect_garbage(&mut self) -> usize {
+        let live: HashSet<TextureId> = self
+            .ctx
+            .tex_manager()
+            .read()
+            .allocated()
+            .map(|(tid, _)| *tid)
+            .collect();
+
+        self.tex_man.retain(|tid| live.contains(tid))
+    }
+
+    ///
+    /// copy what's in the back buffer right now into an egui texture, e.g. to show it blurred behind a menu.
+    ///
+    /// every call returns the same id, its contents are replaced and uploaded with the next frame.
+    /// call it before `present` to get the game without the overlay. multisampled back buffers are
+    /// resolved first, anything but 32-bit RGB ones fail.
+    /// this is slow: reading the back buffer waits for the gpu to finish everything queued so far,
+    /// then the whole image is copied on the cpu and uploaded again. that's several milliseconds at 1080p,
+    /// so capture once when your menu opens instead of every frame if you can.
+    ///
+    pub fn capture_backbuffer_to_texture(
+        &mut self,
+        dev: &IDirect3DDevice9,
+    ) -> Result<TextureId, EguiDx9Error> {
+        let image = self
+            .render_opts
+            .back_buffer
+            .read_pixels(dev)
+            .map_err(|err| EguiDx9Error::new("unable to read back buffer", err))?;
+
+        let handle = match self.capture.take() {
+            Some(mut handle) => {
+                handle.set(image, TextureOptions::LINEAR);
+                handle
+            }
+            None => self
+                .ctx
+                .load_texture("egui-d3d9 back buffer", image, TextureOptions::LINEAR),
+        };
+
+        let id = handle.id();
+        self.capture = Some(handle);
+        // the upload happens with the next frame that runs the UI.
+        self.next_repaint = Some(Instant::now());
+
+        Ok(id)
+    }
+
+    ///
+    /// sample a texture with sRGB decoding on or off, instead of following `set_srgb_framebuffer`.
+    /// `None` goes back to following it, which is what every texture does by default.
+    ///
+    /// with an sRGB framebuffer, blending happens in linear space and every texture is decoded,
+    /// so pass `false` for textures that already hold linear data. without one, blending happens
+    /// in gamma space, and `true` makes an image darker unless its pixels were gamma-encoded twice.
+    /// the flag sticks to the id until egui frees it, so it can be set before the texture is uploaded.
+    /// returns false and changes nothing if the device can't decode sRGB textures.
+    ///
+    pub fn set_texture_srgb(&mut self, id: TextureId, srgb: Option<bool>) -> bool {
+        self.tex_man.set_srgb(id, srgb)
+    }
+
+    ///
+    /// the d3d9 texture behind an egui `TextureId`, e.g. to use the font atlas in your own rendering.
+    ///
+    /// this is a new reference to the same COM object, so holding on to it keeps the texture alive,
+    /// but we may drop and replace ours at any time: when egui frees or resizes it, or around a `Reset`.
+    /// unless `set_managed_textures` is on, it lives in `D3DPOOL_DEFAULT`, so release yours before resetting the device.
+    /// returns `None` if the id is unknown or we're between `pre_reset` and `post_reset`.
+    ///
+    pub fn texture_handle(&self, id: TextureId) -> Option<IDirect3DTexture9> {
+        self.tex_man.get_by_id(id).cloned()
+    }
+
+    ///
+    /// in reactive mode, don't even run the UI while nothing happens. off by default.
+    ///
+    /// with no input, no resize and no repaint due, the last frame's geometry is drawn as-is,
+    /// skipping `ctx.run` and tessellation entirely. that also means your UI closure isn't called.
+    /// a `request_repaint` from another thread is only picked up with the next input
+    /// or when a repaint egui scheduled itself comes due.
+    ///
+    pub fn set_skip_idle_frames(&mut self, skip: bool) {
+        self.skip_idle_frames = skip;
+    }
+
+    ///
+    /// the viewport we drew with in the last `present`, in back buffer pixels.
+    ///
+    /// use it to line your own draws up with egui's. all zero before the first `present`.
+    ///
+    pub fn viewport(&self) -> D3DVIEWPORT9 {
+        self.viewport
+    }
+
+    ///
+    /// whether our application is in the foreground, as tracked through `WM_ACTIVATEAPP`.
+    ///
+    pub fn is_app_active(&self) -> bool {
+        self.input_man.is_app_active()
+    }
+
+    ///
+    /// feed text into egui on the next frame, as if it was typed or dropped in.
+    ///
+    pub fn inject_text(&mut self, text: &str) {
+        self.input_man.push_event(Event::Text(text.to_owned()));
+    }
+
+    ///
+    /// capture modifiers, focus and pointer state, e.g. to put them back later with `restore_input`.
+    ///
+    /// see `InputSnapshot` for what's in it. events that haven't reached egui yet aren't.
+    ///
+    pub fn input_snapshot(&self) -> InputSnapshot {
+        InputSnapshot {
+            pointer: self.ctx.input(|input| input.pointer.clone()),
+            ..self.input_man.snapshot()
+        }
+    }
+
+    ///
+    /// put back input state captured by `input_snapshot`. events queued since are kept.
+    ///
+    pub fn restore_input(&mut self, snapshot: &InputSnapshot) {
+        self.input_man.restore(snapshot);
+        self.ctx
+            .input_mut(|input| input.pointer = snapshot.pointer.clone());
+    }
+
+    ///
+    /// forget all input state, e.g. after the host showed a native dialog that took over the window.
+    ///
+    /// drops queued events and held modifiers, releases every pointer button egui thinks is down
+    /// and ends drags in progress. the next frame starts as if the pointer just left the window.
+    ///
+    pub fn reset_input(&mut self) {
+        self.input_man.reset();
+        // buttons released while the dialog had the mouse never reached us.
+        self.ctx
+            .input_mut(|input| input.pointer = Default::default());
+    }
+
+    ///
+    /// feed a key press or release into egui on the next frame.
+    ///
+    pub fn inject_key(&mut self, key: Key, pressed: bool, modifiers: Modifiers) {
+        self.input_man.push_event(Event::Key {
+            key,
+            pressed,
+            repeat: false,
+            modifiers,
+        });
+    }
+
+    ///
+    /// feed a window message to egui. returns what kind of input it was,
+    /// so your hook can decide per message whether to pass it on to the game.
+    ///
+    #[inline]
+    pub fn wnd_proc(&mut self, umsg: u32, wparam: WPARAM, lparam: LPARAM) -> InputResult {
+        // safe. we only write here, and only read elsewhere.
+        let result = self.input_man.process(umsg, wparam.0, lparam.0);
+
+        if self.input_man.take_theme_change() && self.follow_system_theme {
+            self.apply_system_theme();
+        }
+
+        result
+    }
+}
+
+impl<T, R> EguiDx9<T, R> {
+    /// leaves the visuals alone if the OS doesn't have a dark mode.
+    fn apply_system_theme(&mut self) {
+        if let Some(dark) = self.input_man.system_dark_mode() {
+            self.set_dark_mode(dark);
+        }
+    }
+
+    /// adds `name` to our font definitions, first in `families` if `primary`, last otherwise.
+    fn insert_font(
+        &mut self,
+        name: &str,
+        bytes: Cow<'static, [u8]>,
+        families: &[FontFamily],
+        tweak: Option<FontTweak>,
+        primary: bool,
+    ) {
+        insert_font(&mut self.fonts, name, bytes, families, tweak, primary);
+        self.ctx.set_fonts(self.fonts.clone());
+    }
+
+    /// the size egui lays out in, its pixels_per_point, and how client positions scale into it.
+    /// `None` while the window has no client area.
+    fn frame_size(&mut self, dev: &IDirect3DDevice9) -> Option<(f32, f32, f32, Vec2)> {
+        // query the client size once, so the projection and the input coordinate space agree.
+        let (client_w, client_h) = self.get_screen_size();
+
+        if client_w <= 0. || client_h <= 0. {
+            return None;
+        }
+
+        // zooming in shrinks how many points fit on screen, and grows each one in pixels to match.
+        let (w, h) = self.screen_size.unwrap_or((client_w, client_h));
+        let scale = self.ui_scale();
+        let (w, h) = (w / scale, h / scale);
+        let pointer_scale = Vec2::new(w / client_w, h / client_h);
+
+        let ppp = self
+            .pixels_per_point
+            .map(|ppp| ppp * scale)
+            .unwrap_or_else(|| self.back_buffer_scale(dev, w));
+
+        if ppp != self.render_opts.pixels_per_point {
+            self.render_opts.pixels_per_point = ppp;
+            // cached scissor rects were scaled for the old value.
+            self.stale_buffers = true;
+        }
+
+        Some((w, h, ppp, pointer_scale))
+    }
+
+    /// runs the UI and handles everything in its output except for shapes and freeing textures,
+    /// which have to wait until whatever was tessellated with them is drawn.
+    fn run_ui(
+        &mut self,
+        dev: &IDirect3DDevice9,
+        w: f32,
+        h: f32,
+        ppp: f32,
+        pointer_scale: Vec2,
+    ) -> (FullOutput, Option<R>) {
+        self.begin_ui(w, h, ppp, pointer_scale);
+
+        // safe. present will never run in parallel.
+        let value = Some((self.ui_fn)(&self.ctx, &mut self.ui_state));
+        self.show_debug_overlay();
+
+        let mut output = self.ctx.end_frame();
+        self.finish_ui(dev, &mut output, w, h, pointer_scale);
+
+        (output, value)
+    }
+
+    /// see `set_debug_overlay`. has to run inside the frame, after the user's UI.
+    fn show_debug_overlay(&self) {
+        if !self.debug_overlay {
+            return;
+        }
+
+        let dt = self.ctx.input(|input| input.unstable_dt);
+        let vertices: usize = self.prims.iter().map(|prim| prim.vertices).sum();
+        let indices: usize = self.prims.iter().map(|prim| prim.indices).sum();
+        let draw_calls: usize = self.prims.iter().map(|prim| prim.ranges.len()).sum();
+
+        egui::Area::new("egui-d3d9 debug overlay")
+            .anchor(egui::Align2::RIGHT_TOP, Vec2::new(-4., 4.))
+            .order(Order::Debug)
+            .interactable(false)
+            .show(&self.ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.monospace(format!(
+                        "{:.2} ms ({:.0} fps)",
+                        dt * 1000.,
+                        1. / dt.max(f32::EPSILON)
+                    ));
+                    ui.monospace(format!("{draw_calls} draw calls"));
+                    ui.monospace(format!("{vertices} vertices, {} triangles", indices / 3));
+                    ui.monospace(format!(
+                        "{} textures, {:.1} MB",
+                        self.tex_man.count(),
+                        self.tex_man.bytes() as f32 / (1024. * 1024.)
+                    ));
+                });
+            });
+    }
+
+    /// collects input and starts an egui frame with it.
+    fn begin_ui(&mut self, w: f32, h: f32, ppp: f32, pointer_scale: Vec2) {
+        self.last_frame = Some(Instant::now());
+        self.last_size = Some((w, h));
+
+        let input = self.input_man.collect_input(w, h, ppp, pointer_scale);
+
+        if self.record_input {
+            self.last_input = Some(input.clone());
+        }
+
+        self.ctx.begin_frame(input);
+        mark_orders(&self.ctx);
+    }
+
+    /// hands everything in a finished frame's output except shapes and freed textures
+    /// to where it's going: input state, repaint scheduling, texture uploads, IME, clipboard.
+    #[cfg_attr(not(feature = "ime"), allow(unused_variables))]
+    fn finish_ui(
+        &mut self,
+        dev: &IDirect3DDevice9,
+        output: &mut FullOutput,
+        w: f32,
+        h: f32,
+        pointer_scale: Vec2,
+    ) {
+        self.input_man
+            .set_wants_keyboard(self.ctx.wants_keyboard_input());
+        self.input_man
+            .set_wants_pointer(self.ctx.wants_pointer_input());
+        self.input_man
+            .set_cursor_icon(output.platform_output.cursor_icon);
+
+        self.animating = output.repaint_after.is_zero();
+        // egui says "never" with Duration::MAX, which doesn't fit in an Instant.
+        self.next_repaint = Instant::now().checked_add(output.repaint_after);
+
+        if !output.textures_delta.is_empty() {
+            self.tex_man.process_set_deltas(dev, &output.textures_delta);
+        }
+
+        #[cfg(feature = "ime")]
+        if let Some(pos) = output.platform_output.text_cursor_pos {
+            let pos = self.render_opts.physical_pos(pos, w, h);
+
+            crate::ime::set_composition_pos(
+                self.hwnd,
+                Pos2::new(pos.x / pointer_scale.x, pos.y / pointer_scale.y),
+            );
+        }
+
+        self.input_man
+            .set_clipboard_text(std::mem::take(&mut output.platform_output.copied_text));
+    }
+
+    /// tessellates, uploads and draws what the UI produced, then frees the textures egui is done with.
+    /// the other contexts run here, so their meshes are drawn after ours.
+    fn draw_output(
+        &mut self,
+        dev: &IDirect3DDevice9,
+        output: FullOutput,
+        w: f32,
+        h: f32,
+        repaint_due: bool,
+        layers: &[Order],
+    ) -> PresentStatus {
+        let painted = split_by_order(output.shapes);
+
+        if painted.is_empty() && self.contexts.is_empty() {
+            // early return, don't forget to free textures
+            if !output.textures_delta.is_empty() {
+                self.tex_man.process_free_deltas(&output.textures_delta);
+            }
+            // so throttled frames don't bring back what was drawn before.
+            self.prims.clear();
+            return PresentStatus::EmptyFrame;
+        }
+
+        // we only need to update the buffers if we are actually changing something
+        // a repaint egui scheduled (a tooltip showing up, a blinking cursor) counts even in reactive mode.
+        let update_buffers = std::mem::take(&mut self.stale_buffers)
+            || output.repaint_after.is_zero()
+            || repaint_due
+            || !self.reactive;
+
+        if update_buffers {
+            let linear_colors = self.render_opts.srgb_framebuffer;
+            let color_order = self.render_opts.color_order;
+
+            let mut meshes = tessellate(&self.ctx, painted, &self.render_opts, w, h);
+
+            // the other contexts go after ours, in the order they were added.
+            let extra_deltas = self.run_contexts(dev, &mut meshes, w, h);
+
+            // size the buffers from the totals up front, then write every mesh straight into them.
+            // this skips building one big intermediate vertex and index `Vec` every frame,
+            // which adds up quickly for UIs with hundreds of thousands of vertices.
+            let vtx_count = meshes.iter().map(|(desc, _)| desc.vertices).sum();
+            let idx_count = meshes.iter().map(|(desc, _)| desc.indices).sum();
+
+            let (min_vtx, min_idx) = self.min_buffer_size;
+            if let Err(err) =
+                self.buffers
+                    .reserve(dev, vtx_count.max(min_vtx), idx_count.max(min_idx))
+            {
+                // the frame is too large to ever fit, drop it instead of drawing stale geometry.
+                warn!("{}", err);
+                self.last_error = Some(err);
+                self.prims.clear();
+                self.tex_man.process_free_deltas(&output.textures_delta);
+                self.free_context_textures(&extra_deltas);
+                return PresentStatus::EmptyFrame;
+            }
+
+            self.buffers.write_vertices(vtx_count, |buffer| {
+                let mut offset = 0;
+                meshes.iter().for_each(|(_, mesh)| {
+                    buffer[offset..offset + mesh.vertices.len()]
+                        .iter_mut()
+                        .zip(mesh.vertices.iter())
+                        .for_each(|(dst, v)| *dst = GpuVertex::new(v, linear_colors, color_order));
+                    offset += mesh.vertices.len();
+                });
+            });
+
+            self.buffers.write_indices(idx_count, |buffer| {
+                let mut offset = 0;
+                meshes.iter().for_each(|(_, mesh)| {
+                    buffer[offset..offset + mesh.indices.len()].copy_from_slice(&mesh.indices);
+                    offset += mesh.indices.len();
+                });
+            });
+
+            self.prims = meshes
+                .into_iter()
+                .map(|(mut desc, mesh)| {
+                    desc.ranges =
+                        draw_ranges(&mesh.indices, self.max_draw_prims, self.max_draw_vertices);
+                    desc
+                })
+                .collect();
+
+            self.draw(dev, w, h, layers);
+            self.free_context_textures(&extra_deltas);
+        } else {
+            self.draw(dev, w, h, layers);
+        }
+
+        if !output.textures_delta.is_empty() {
+            self.tex_man.process_free_deltas(&output.textures_delta);
+        }
+
+        if update_buffers {
+            PresentStatus::Drawn
+        } else {
+            PresentStatus::SkippedReactive
+        }
+    }
+
+    /// runs the other contexts and adds their meshes to `meshes`. they get the screen and time
+    /// of the frame we just ran, but no input.
+    /// returns their texture deltas, which have to be freed once they're drawn.
+    fn run_contexts(
+        &mut self,
+        dev: &IDirect3DDevice9,
+        meshes: &mut Vec<(MeshDescriptor, Mesh)>,
+        w: f32,
+        h: f32,
+    ) -> Vec<TexturesDelta> {
+        let input = self.ctx.input(|input| RawInput {
+            screen_rect: Some(input.screen_rect),
+            pixels_per_point: Some(input.pixels_per_point),
+            time: Some(input.time),
+            ..Default::default()
+        });
+        let opts = &self.render_opts;
+
+        self.contexts
+            .iter_mut()
+            .enumerate()
+            .map(|(index, extra)| {
+                let output = extra.ctx.run(input.clone(), |ctx| {
+                    mark_orders(ctx);
+                    (extra.ui_fn)(ctx);
+                });
+
+                extra
+                    .tex_man
+                    .process_set_deltas(dev, &output.textures_delta);
+
+                let painted = split_by_order(output.shapes);
+                meshes.extend(tessellate(&extra.ctx, painted, opts, w, h).into_iter().map(
+                    |(mut desc, mesh)| {
+                        desc.context = Some(index);
+                        (desc, mesh)
+                    },
+                ));
+
+                output.textures_delta
+            })
+            .collect()
+    }
+
+    fn free_context_textures(&mut self, deltas: &[TexturesDelta]) {
+        self.contexts
+            .iter_mut()
+            .zip(deltas)
+            .for_each(|(extra, delta)| extra.tex_man.process_free_deltas(delta));
+    }
+
+    /// the client size, or the last one we got if `GetClientRect` fails.
+    /// `(0, 0)` only if it never worked, which `present` skips as hidden.
+    fn get_screen_size(&mut self) -> (f32, f32) {
+        let size = match client_size(self.hwnd) {
+            Ok(size) => size,
+            Err(err) => {
+                warn!("GetClientRect failed, using the last known size: {}", err);
+                return self.client_size.unwrap_or_default();
+            }
+        };
+
+        self.client_size = Some(size);
+        self.input_man.set_client_size(Vec2::new(size.0, size.1));
+        size
+    }
+
+    /// draws the cached geometry. failures skip the rest of the frame instead of panicking,
+    /// and are kept around for `last_error`.
+    fn draw(&mut self, dev: &IDirect3DDevice9, w: f32, h: f32, layers: &[Order]) {
+        let ppp = self.render_opts.pixels_per_point;
+        self.viewport = Self::get_viewport(w * ppp, h * ppp);
+
+        self.draw_layers(dev, layers);
+    }
+
+    fn draw_layers(&mut self, dev: &IDirect3DDevice9, layers: &[Order]) {
+        self.drawn_orders.extend_from_slice(layers);
+
+        if let Err(err) = self.try_draw(dev, layers) {
+            // this tends to fail the same way every frame, `last_error` always has the latest.
+            static WARNED: Once = Once::new();
+            WARNED.call_once(|| warn!("{}", err));
+            self.last_error = Some(err);
+        }
+
+        // the game's state is back by now, `DxState` is dropped at the end of `try_draw`.
+        if let Some(post_render) = self.post_render.as_mut() {
+            post_render(dev);
+        }
+    }
+
+    fn try_draw(&self, dev: &IDirect3DDevice9, layers: &[Order]) -> Result<(), EguiDx9Error> {
+        // every path refills stale buffers before drawing, so this should never trip,
+        // but drawing from freed buffers is not something to risk.
+        let Some((vtx, idx)) =
+            drawable_buffers(&self.buffers, self.should_reset, self.stale_buffers)
+        else {
+            return Ok(());
+        };
+
+        if !self.prims.iter().any(|mesh| layers.contains(&mesh.order)) {
+            return Ok(());
+        }
+
+        if let Some(surface) = &self.target_surface {
+            let mut desc = D3DSURFACE_DESC::default();
+
+            unsafe { surface.GetDesc(&mut desc) }
+                .map_err(|e| EguiDx9Error::new("unable to describe target surface", e))?;
+
+            if !covers_viewport(&desc, &self.viewport) {
+                return Err(EguiDx9Error::new(
+                    "target surface is smaller than the viewport",
+                    E_INVALIDARG.into(),
+                ));
+            }
+        }
+
+        // back up our state so we don't mess with the game and the game doesn't mess with us.
+        // i actually had the idea to use BeginStateBlock and co. to "cache" the state we set every frame,
+        // and just re-applying it everytime. just setting this manually takes around 50 microseconds on my machine.
+        let _state = DxState::setup(
+            dev,
+            self.viewport,
+            &self.render_opts,
+            self.target_surface.as_ref(),
+        );
+
+        unsafe {
+            dev.SetStreamSource(0, vtx, 0, std::mem::size_of::<GpuVertex>() as _)
+                .map_err(|e| EguiDx9Error::new("unable to set vertex stream source", e))?;
+
+            dev.SetIndices(idx)
+                .map_err(|e| EguiDx9Error::new("unable to set index buffer", e))?;
+        }
+
+        // `DxState` set up ours, the other contexts come after it.
+        let mut context = None;
+        // what `setup_state` left the sampler at.
+        let mut srgb = self.render_opts.srgb_framebuffer;
+
+        // the other contexts' texture ids overlap ours, look each one up where its mesh came from.
+        let tex_man = |context: Option<usize>| match context {
+            Some(index) => &self.contexts[index].tex_man,
+            None => &self.tex_man,
+        };
+        let find_texture = |mesh: &MeshDescriptor| tex_man(mesh.context).get_by_id(mesh.texture_id);
+
+        for (mesh, texture, vtx_start, idx_start) in mesh_draws(&self.prims, layers, find_texture) {
+            unsafe {
+                if mesh.context != context {
+                    context = mesh.context;
+
+                    let custom = context.and_then(|index| self.contexts[index].projection);
+                    let ((mat_world, mat_view, mat_proj), scissor) =
+                        context_transform(&self.viewport, &self.render_opts, custom);
+
+                    dev.SetTransform(D3DTRANSFORMSTATETYPE(0 + 256), &mat_world)
+                        .map_err(|e| EguiDx9Error::new("unable to set world matrix", e))?;
+                    dev.SetTransform(D3DTS_VIEW, &mat_view)
+                        .map_err(|e| EguiDx9Error::new("unable to set view matrix", e))?;
+                    dev.SetTransform(D3DTS_PROJECTION, &mat_proj)
+                        .map_err(|e| EguiDx9Error::new("unable to set projection matrix", e))?;
+                    dev.SetRenderState(D3DRS_SCISSORTESTENABLE, scissor as _)
+                        .map_err(|e| EguiDx9Error::new("unable to set scissor test", e))?;
+                }
+
+                if self.render_opts.hardware_scissor() {
+                    dev.SetScissorRect(&mesh.clip)
+                        .map_err(|e| EguiDx9Error::new("unable to set scissor rect", e))?;
+                }
+
+                dev.SetTexture(0, texture)
+                    .map_err(|e| EguiDx9Error::new("unable to set texture", e))?;
+
+                let texture_srgb = tex_man(mesh.context)
+                    .is_srgb(mesh.texture_id)
+                    .unwrap_or(self.render_opts.srgb_framebuffer);
+
+                if texture_srgb != srgb {
+                    dev.SetSamplerState(0, D3DSAMP_SRGBTEXTURE, texture_srgb as _)
+                        .map_err(|e| EguiDx9Error::new("unable to set srgb sampling", e))?;
+                    srgb = texture_srgb;
+                }
+
+                // some devices can't take all of a large mesh in one call, split it up for those.
+                for range in mesh.ranges.iter() {
+                    dev.DrawIndexedPrimitive(
+                        D3DPT_TRIANGLELIST,
+                        vtx_start as _,
+                        range.min_vertex,
+                        range.num_vertices,
+                        (idx_start + range.first) as _,
+                        range.triangles as _,
+                    )
+                    .map_err(|e| EguiDx9Error::new("unable to draw indexed prims", e))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_throttled(&self) -> bool {
+        match (self.frame_interval, self.last_frame) {
+            (Some(interval), Some(last_frame)) => last_frame.elapsed() < interval,
+            _ => false,
+        }
+    }
+
+    /// nothing happened since the last frame that ran the UI: no input, no resize,
+    /// and no repaint egui asked for has come due.
+    fn is_idle(&self, w: f32, h: f32) -> bool {
+        !self.animating
+            && !self.input_man.has_events()
+            && self.last_size == Some((w, h))
+            && !self.is_repaint_due()
+    }
+
+    /// client pixels per point before `pixels_per_point`, the baseline times the zoom.
+    fn ui_scale(&self) -> f32 {
+        self.initial_scale * self.zoom_factor
+    }
+
+    fn is_repaint_due(&self) -> bool {
+        repaint_due(self.next_repaint, Instant::now())
+    }
+
+    /// back buffer pixels per client pixel, so text stays crisp in games with a render scale.
+    fn back_buffer_scale(&self, dev: &IDirect3DDevice9, w: f32) -> f32 {
+        let bb_w = match &self.target_surface {
+            Some(surface) => {
+                let mut desc = D3DSURFACE_DESC::default();
+                dx_expect!(
+                    unsafe { surface.GetDesc(&mut desc) },
+                    "unable to describe target surface"
+                );
+                desc.Width
+            }
+            None => {
+                dx_expect!(
+                    self.render_opts.back_buffer.size(dev),
+                    "unable to get back buffer size"
+                )
+                .0
+            }
+        };
+
+        if bb_w == 0 {
+            1.
+        } else {
+            bb_w as f32 / w
+        }
+    }
+
+    /// how many triangles, and how many vertices, the device takes in a single draw call.
+    fn get_draw_limits(dev: &IDirect3DDevice9) -> (usize, u32) {
+        let mut caps = D3DCAPS9::default();
+
+        unsafe {
+            dx_expect!(dev.GetDeviceCaps(&mut caps), "unable to get device caps");
+        }
+
+        (
+            (caps.MaxPrimitiveCount as usize).max(1),
+            caps.MaxVertexIndex.saturating_add(1),
+        )
+    }
+
+    fn get_viewport(w: f32, h: f32) -> D3DVIEWPORT9 {
+        D3DVIEWPORT9 {
+            X: 0,
+            Y: 0,
+            Width: w as _,
+            Height: h as _,
+            MinZ: 0.,
+            MaxZ: 1.,
+        }
+    }
+}
+
+impl EguiDx9<()> {
+    ///
+    /// draw a single frame without keeping a backend around, e.g. for a loading screen.
+    ///
+    /// everything (buffers, textures, the egui context) is created and torn down within the call,
+    /// so don't use this every frame. keep an `EguiDx9` around for that instead.
+    ///
+    pub fn render_once(
+        dev: &IDirect3DDevice9,
+        hwnd: HWND,
+        ui_fn: impl FnOnce(&Context) + 'static,
+    ) -> PresentStatus {
+        let mut ui_fn = Some(ui_fn);
+
+        let mut app = Self::init(
+            dev,
+            hwnd,
+            move |ctx, _| {
+                if let Some(ui_fn) = ui_fn.take() {
+                    ui_fn(ctx)
+                }
+            },
+            (),
+            false,
+        );
+
+        app.present(dev).status
+    }
+}
+
+impl<T, R> Drop for EguiDx9<T, R> {
+    fn drop(&mut self) {
+        self.buffers.delete_buffers();
+        self.tex_man.deallocate_textures();
+        self.contexts
+            .iter_mut()
+            .for_each(|extra| extra.tex_man.deallocate_textures());
+    }
+}
+
+/// set while `present` runs, a nested call finds it set and backs off.
+/// clearing it on drop means a panicking ui can't leave us skipping every frame after.
+struct PresentGuard(Rc<Cell<bool>>);
+
+impl PresentGuard {
+    fn enter(presenting: &Rc<Cell<bool>>) -> Option<Self> {
+        if presenting.replace(true) {
+            None
+        } else {
+            Some(Self(presenting.clone()))
+        }
+    }
+}
+
+impl Drop for PresentGuard {
+    fn drop(&mut self) {
+        self.0.set(false);
+    }
+}
+
+/// whether `hwnd` no longer names a window, e.g. because it was destroyed under us.
+fn window_gone(hwnd: HWND) -> bool {
+    !unsafe { IsWindow(hwnd) }.as_bool()
+}
+
+fn insert_font(
+    fonts: &mut FontDefinitions,
+    name: &str,
+    bytes: Cow<'static, [u8]>,
+    families: &[FontFamily],
+    tweak: Option<FontTweak>,
+    primary: bool,
+) {
+    if name.is_empty() || families.is_empty() {
+        panic!("invalid font specified in egui add_font");
+    }
+
+    fonts.font_data.insert(
+        name.to_owned(),
+        FontData {
+            font: bytes,
+            index: 0,
+            tweak: tweak.unwrap_or_default(),
+        },
+    );
+
+    for family in families {
+        let names = fonts.families.entry(family.clone()).or_default();
+        names.retain(|font| font != name);
+
+        if primary {
+            names.insert(0, name.to_owned());
+        } else {
+            names.push(name.to_owned());
+        }
+    }
+}
+
+/// the buffer sizes to recreate after a reset: what was drawn last, so the first frame after it
+/// doesn't grow them again. that frame is still tessellated before anything is written,
+/// and grows them if it's larger.
+fn reset_buffer_size(
+    prims: &[MeshDescriptor],
+    (min_vtx, min_idx): (usize, usize),
+) -> (usize, usize) {
+    let vertices = prims.iter().map(|prim| prim.vertices).sum::<usize>();
+    let indices = prims.iter().map(|prim| prim.indices).sum::<usize>();
+
+    (vertices.max(min_vtx), indices.max(min_idx))
+}
+
+/// the meshes `layers` draws, each with its texture and its first vertex and index in the buffers.
+/// meshes whose texture we don't have are skipped: one widget missing this frame beats taking the host down with it.
+fn mesh_draws<'a, T: 'a>(
+    prims: &'a [MeshDescriptor],
+    layers: &'a [Order],
+    texture: impl Fn(&MeshDescriptor) -> Option<T> + 'a,
+) -> impl Iterator<Item = (&'a MeshDescriptor, T, usize, usize)> + 'a {
+    prims
+        .iter()
+        .scan((0, 0), |(vtx, idx), mesh| {
+            let start = (*vtx, *idx);
+            *vtx += mesh.vertices;
+            *idx += mesh.indices;
+            Some((mesh, start))
+        })
+        .filter(move |(mesh, _)| layers.contains(&mesh.order))
+        .filter_map(move |(mesh, (vtx_start, idx_start))| {
+            let Some(texture) = texture(mesh) else {
+                static WARNED: Once = Once::new();
+                WARNED.call_once(|| warn!("skipping meshes that use a texture we don't have"));
+                return None;
+            };
+
+            Some((mesh, texture, vtx_start, idx_start))
+        })
+}
+
+/// whether `surface` has room for everything `viewport` draws.
+fn covers_viewport(surface: &D3DSURFACE_DESC, viewport: &D3DVIEWPORT9) -> bool {
+    viewport.X + viewport.Width <= surface.Width && viewport.Y + viewport.Height <= surface.Height
+}
+
+fn repaint_due(next_repaint: Option<Instant>, now: Instant) -> bool {
+    // `None` is egui asking to never be repainted on its own.
+    next_repaint.is_some_and(|at| now >= at)
+}
+
+/// tessellates a frame's shapes an order at a time, so every mesh knows which `present_layer` draws it.
+fn tessellate(
+    ctx: &Context,
+    painted: Vec<(Order, Vec<ClippedShape>)>,
+    opts: &RenderOptions,
+    w: f32,
+    h: f32,
+) -> Vec<(MeshDescriptor, Mesh)> {
+    painted
+        .into_iter()
+        .flat_map(|(order, shapes)| {
+            ctx.tessellate(shapes)
+                .into_iter()
+                .map(move |prim| (order, prim))
+        })
+        .filter_map(|(order, prim)| {
+            let mesh = drawable_mesh(prim.primitive)?;
+            if opts.cull_transparent && is_transparent(&mesh) {
+                return None;
+            }
+
+            let mut mesh = if opts.scissor && opts.cpu_clip {
+                clip_mesh(mesh, prim.clip_rect)
+            } else {
+                mesh
+            };
+            let clip = opts.physical_clip(prim.clip_rect, w, h);
+
+            MeshDescriptor::from_mesh(&mut mesh, clip).map(|mut desc| {
+                desc.order = order;
+                (desc, mesh)
+            })
+        })
+        .collect()
+}
+
+fn client_size(hwnd: HWND) -> windows::core::Result<(f32, f32)> {
+    let mut rect = RECT::default();
+    unsafe { GetClientRect(hwnd, &mut rect) }?;
+
+    Ok((
+        (rect.right - rect.left) as f32,
+        (rect.bottom - rect.top) as f32,
+    ))
+}
+
+/// the buffers, if they still hold what `prims` describes.
+/// after `pre_reset` they're gone, after `post_reset` (`stale_buffers`) they're empty until refilled.
+fn drawable_buffers(
+    buffers: &Buffers,
+    should_reset: bool,
+    stale_buffers: bool,
+) -> Option<(&IDirect3DVertexBuffer9, &IDirect3DIndexBuffer9)> {
+    if should_reset || stale_buffers {
+        return None;
+    }
+
+    Some((buffers.vtx.as_ref()?, buffers.idx.as_ref()?))
+}
+
+/// the mesh of a primitive we know how to draw, the others are skipped with a warning.
+fn drawable_mesh(primitive: Primitive) -> Option<Mesh> {
+    match primitive {
+        Primitive::Mesh(mesh) => Some(mesh),
+        Primitive::Callback(_) => {
+            static WARNED: Once = Once::new();
+            WARNED.call_once(|| warn!("paint callbacks aren't supported, skipping them"));
+            None
+        }
+        // in case a newer egui adds primitives we don't know how to draw.
+        #[allow(unreachable_patterns)]
+        _ => {
+            static WARNED: Once = Once::new();
+            WARNED.call_once(|| warn!("skipping an unknown kind of primitive"));
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use windows::{
+        core::w,
+        Win32::{
+            Foundation::HINSTANCE,
+            UI::WindowsAndMessaging::{
+                CreateWindowExW, DestroyWindow, HMENU, WINDOW_EX_STYLE, WS_POPUP,
+            },
+        },
+    };
+
+    #[test]
+    fn destroyed_windows_are_noticed() {
+        assert!(window_gone(HWND(0)));
+
+        let hwnd = unsafe {
+            CreateWindowExW(
+                WINDOW_EX_STYLE::default(),
+                w!("STATIC"),
+                w!("egui-d3d9 test"),
+                WS_POPUP,
+                0,
+                0,
+                100,
+                100,
+                HWND::default(),
+                HMENU::default(),
+                HINSTANCE::default(),
+                None,
+            )
+        };
+        assert!(!window_gone(hwnd));
+
+        // present checks this before anything touches the window, so it skips instead of panicking.
+        expect!(unsafe { DestroyWindow(hwnd) }, "unable to destroy window");
+        assert!(window_gone(hwnd));
+    }
+
+    #[test]
+    fn reentrant_present_is_ignored() {
+        let presenting = Rc::default();
+
+        let outer = PresentGuard::enter(&presenting).expect("first present was refused");
+
+        // a hook calling present again from inside the ui or the draw.
+        assert!(PresentGuard::enter(&presenting).is_none());
+        assert!(
+            presenting.get(),
+            "the nested call cleared the outer one's flag"
+        );
+
+        drop(outer);
+        assert!(PresentGuard::enter(&presenting).is_some());
+    }
+
+    #[test]
+    fn paint_callbacks_are_skipped() {
+        let callback = Primitive::Callback(egui::PaintCallback {
+            rect: Rect::NOTHING,
+            callback: std::sync::Arc::new(()),
+        });
+        assert!(drawable_mesh(callback).is_none());
+
+        let mesh = Mesh::with_texture(TextureId::Managed(0));
+        assert_eq!(drawable_mesh(Primitive::Mesh(mesh.clone())), Some(mesh));
+    }
+
+    #[test]
+    fn freed_or_stale_buffers_are_not_drawn_from() {
+        // what `pre_reset` leaves behind.
+        let mut buffers = Buffers::default();
+        buffers.delete_buffers();
+
+        for (should_reset, stale_buffers) in [(true, false), (false, true), (false, false)] {
+            assert!(drawable_buffers(&buffers, should_reset, stale_buffers).is_none());
+        }
+    }
+
+    #[test]
+    fn client_size_fails_without_a_window() {
+        let hwnd = unsafe {
+            CreateWindowExW(
+                WINDOW_EX_STYLE::default(),
+                w!("STATIC"),
+                w!("egui-d3d9 test"),
+                WS_POPUP,
+                0,
+                0,
+                100,
+                50,
+                HWND::default(),
+                HMENU::default(),
+                HINSTANCE::default(),
+                None,
+            )
+        };
+        assert_eq!(client_size(hwnd).ok(), Some((100., 50.)));
+
+        // what `get_screen_size` falls back to the last size for.
+        expect!(unsafe { DestroyWindow(hwnd) }, "unable to destroy window");
+        assert!(client_size(hwnd).is_err());
+    }
+
+    #[test]
+    fn requested_repaints_come_due() {
+        let ctx = Context::default();
+        let now = Instant::now();
+
+        // egui repaints right away for a few frames after starting up.
+        for _ in 0..3 {
+            let _ = ctx.run(RawInput::default(), |_| {});
+        }
+
+        // a tooltip waiting to show up.
+        let output = ctx.run(RawInput::default(), |ctx| {
+            ctx.request_repaint_after(Duration::from_millis(500))
+        });
+        let next_repaint = now.checked_add(output.repaint_after);
+
+        assert!(!repaint_due(next_repaint, now));
+        assert!(repaint_due(next_repaint, now + Duration::from_secs(1)));
+
+        // nothing scheduled, egui only wants input.
+        let output = ctx.run(RawInput::default(), |_| {});
+        let next_repaint = now.checked_add(output.repaint_after);
+
+        assert!(next_repaint.is_none());
+        assert!(!repaint_due(next_repaint, now + Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn fonts_survive_a_new_instance() {
+        let defaults = FontDefinitions::default();
+        let hack = defaults.font_data["Hack"].font.clone();
+
+        // what `add_font` leaves in `fonts`.
+        let mut fonts = defaults;
+        insert_font(
+            &mut fonts,
+            "my mono",
+            hack,
+            &[FontFamily::Proportional],
+            None,
+            true,
+        );
+
+        // a new instance comes with a new context, `set_fonts` hands it the old instance's fonts.
+        let ctx = Context::default();
+        ctx.set_fonts(fonts.clone());
+        let _ = ctx.run(RawInput::default(), |_| {});
+
+        ctx.fonts(|fonts| {
+            let fonts = fonts.lock();
+            let families = &fonts.fonts.definitions().families;
+            assert_eq!(families[&FontFamily::Proportional][0], "my mono");
+        });
+    }
+
+    #[test]
+    fn buffers_after_a_reset_fit_the_last_frame() {
+        // one mesh past the default minimum, like a long text edit or a large image.
+        let mut mesh = Mesh::default();
+        (0..5_000).for_each(|i| {
+            let min = Pos2::new(i as f32 % 800., (i / 800) as f32);
+            mesh.add_colored_rect(
+                Rect::from_min_size(min, Vec2::splat(1.)),
+                egui::Color32::WHITE,
+            );
+        });
+        assert_eq!((mesh.vertices.len(), mesh.indices.len()), (20_000, 30_000));
+
+        let clip = Rect::from_min_size(Pos2::ZERO, Vec2::new(800., 600.));
+        let prims = [MeshDescriptor::from_mesh(&mut mesh, clip).expect("mesh was skipped")];
+
+        assert_eq!(reset_buffer_size(&prims, (16384, 16384)), (20_000, 30_000));
+        // small or empty frames still get the minimum.
+        assert_eq!(reset_buffer_size(&[], (16384, 16384)), (16384, 16384));
+    }
+
+    #[test]
+    fn meshes_without_a_texture_are_skipped() {
+        let clip = Rect::from_min_size(Pos2::ZERO, Vec2::new(800., 600.));
+        let prims = [0, 1, 0].map(|id| {
+            let mut mesh = Mesh::with_texture(TextureId::Managed(id));
+            mesh.add_rect_with_uv(clip, Rect::ZERO, egui::Color32::WHITE);
+            MeshDescriptor::from_mesh(&mut mesh, clip).expect("mesh was skipped")
+        });
+
+        // say creating texture 1 failed, the meshes around it still draw from where they are in the buffers.
+        let have = |mesh: &MeshDescriptor| {
+            (mesh.texture_id == TextureId::Managed(0)).then_some(mesh.texture_id)
+        };
+        let draws = mesh_draws(&prims, &ORDERS, have)
+            .map(|(mesh, texture, vtx, idx)| (mesh.texture_id, texture, vtx, idx))
+            .collect::<Vec<_>>();
+
+        let tid = TextureId::Managed(0);
+        assert_eq!(draws, [(tid, tid, 0, 0), (tid, tid, 8, 12)]);
+    }
+
+    #[test]
+    fn target_surfaces_have_to_cover_the_viewport() {
+        let surface = D3DSURFACE_DESC {
+            Width: 1920,
+            Height: 1080,
+            ..Default::default()
+        };
+        let viewport = |x, y, width, height| D3DVIEWPORT9 {
+            X: x,
+            Y: y,
+            Width: width,
+            Height: height,
+            MinZ: 0.,
+            MaxZ: 1.,
+        };
+
+        assert!(covers_viewport(&surface, &viewport(0, 0, 1920, 1080)));
+        assert!(covers_viewport(&surface, &viewport(0, 0, 1280, 720)));
+        // e.g. a render-scaled game handing us its smaller back buffer.
+        assert!(!covers_viewport(&surface, &viewport(0, 0, 2560, 1440)));
+        // an offset viewport has to fit from where it starts.
+        assert!(!covers_viewport(&surface, &viewport(100, 0, 1920, 1080)));
     }
 }
@@ -0,0 +1,108 @@
+use clipboard::{windows_clipboard::WindowsClipboardContext, ClipboardProvider};
+use windows::Win32::System::DataExchange::GetClipboardSequenceNumber;
+
+/// backing store for copy, cut and paste.
+pub trait Clipboard {
+    fn get(&mut self) -> Option<String>;
+    fn set(&mut self, text: String);
+}
+
+/// the OS clipboard. this is the default.
+///
+/// what we read is cached until the clipboard's sequence number changes,
+/// so holding Ctrl+V doesn't open the clipboard on every repeat.
+pub struct WindowsClipboard {
+    cache: Option<(u32, String)>,
+    /// `GetClipboardSequenceNumber` and a read of the OS clipboard, outside of tests.
+    sequence: fn() -> u32,
+    read: fn() -> Option<String>,
+}
+
+impl Default for WindowsClipboard {
+    fn default() -> Self {
+        Self {
+            cache: None,
+            sequence: || unsafe { GetClipboardSequenceNumber() },
+            read: || WindowsClipboardContext.get_contents().ok(),
+        }
+    }
+}
+
+impl Clipboard for WindowsClipboard {
+    fn get(&mut self) -> Option<String> {
+        // 0 means we aren't allowed to know, so don't trust the cache then.
+        let sequence = (self.sequence)();
+
+        match &self.cache {
+            Some((cached, text)) if sequence != 0 && *cached == sequence => Some(text.clone()),
+            _ => {
+                let text = (self.read)()?;
+                self.cache = Some((sequence, text.clone()));
+                Some(text)
+            }
+        }
+    }
+
+    fn set(&mut self, text: String) {
+        let _ = WindowsClipboardContext.set_contents(text);
+    }
+}
+
+/// a clipboard that only lives inside the overlay.
+///
+/// copy and paste keep working between egui widgets, but the OS clipboard is never opened.
+/// useful in hosts where touching the clipboard is unwanted.
+#[derive(Default)]
+pub struct NullClipboard {
+    contents: Option<String>,
+}
+
+impl Clipboard for NullClipboard {
+    fn get(&mut self) -> Option<String> {
+        self.contents.clone()
+    }
+
+    fn set(&mut self, text: String) {
+        self.contents = Some(text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+    static SEQUENCE: AtomicU32 = AtomicU32::new(1);
+    static READS: AtomicUsize = AtomicUsize::new(0);
+
+    fn clipboard() -> WindowsClipboard {
+        WindowsClipboard {
+            cache: None,
+            sequence: || SEQUENCE.load(Ordering::SeqCst),
+            read: || {
+                let read = READS.fetch_add(1, Ordering::SeqCst);
+                Some(format!("read {read}"))
+            },
+        }
+    }
+
+    #[test]
+    fn reads_are_cached_until_the_sequence_changes() {
+        let mut clipboard = clipboard();
+
+        assert_eq!(clipboard.get().as_deref(), Some("read 0"));
+        assert_eq!(clipboard.get().as_deref(), Some("read 0"));
+        assert_eq!(READS.load(Ordering::SeqCst), 1);
+
+        // something else was copied.
+        SEQUENCE.store(2, Ordering::SeqCst);
+        assert_eq!(clipboard.get().as_deref(), Some("read 1"));
+        assert_eq!(clipboard.get().as_deref(), Some("read 1"));
+        assert_eq!(READS.load(Ordering::SeqCst), 2);
+
+        // not allowed to see the sequence number, every paste reads.
+        SEQUENCE.store(0, Ordering::SeqCst);
+        assert_eq!(clipboard.get().as_deref(), Some("read 2"));
+        assert_eq!(clipboard.get().as_deref(), Some("read 3"));
+    }
+}
@@ -0,0 +1,51 @@
+use egui::{Color32, Rgba};
+
+/// byte order of a packed 32-bit color, as seen in memory.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorOrder {
+    /// what `D3DFMT_A8R8G8B8` and `D3DFVF_DIFFUSE` expect, used by the fixed-function pipeline.
+    /// both are `0xAARRGGBB` read as a little-endian u32, so B, G, R, A in memory.
+    #[default]
+    Bgra,
+    /// for shader pipelines that read colors as-is.
+    Rgba,
+}
+
+impl ColorOrder {
+    pub fn pack(self, [r, g, b, a]: [u8; 4]) -> PackedColor {
+        PackedColor(match self {
+            ColorOrder::Bgra => u32::from_le_bytes([b, g, r, a]),
+            ColorOrder::Rgba => u32::from_le_bytes([r, g, b, a]),
+        })
+    }
+}
+
+/// a color packed in whatever `ColorOrder` the consumer wants.
+/// the channel mapping is done by `ColorOrder::pack`, not by field order.
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PackedColor(pub u32);
+
+/// converts egui's gamma-space color to linear, for when the device does sRGB writes.
+pub fn linear_rgba(color: Color32) -> [u8; 4] {
+    Rgba::from(color)
+        .to_array()
+        .map(|c| (c * 255.).round() as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_in_the_requested_order() {
+        let color = Color32::from_rgba_premultiplied(1, 2, 3, 4).to_array();
+
+        // 0xAARRGGBB, what D3DFMT_A8R8G8B8 and D3DFVF_DIFFUSE expect.
+        assert_eq!(ColorOrder::Bgra.pack(color), PackedColor(0x04010203));
+        assert_eq!(ColorOrder::Rgba.pack(color), PackedColor(0x04030201));
+
+        assert_eq!(ColorOrder::Bgra.pack(color).0.to_le_bytes(), [3, 2, 1, 4]);
+        assert_eq!(ColorOrder::Rgba.pack(color).0.to_le_bytes(), [1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,51 @@
+use std::fmt;
+
+use windows::core::HRESULT;
+
+/// a d3d call that failed without taking the process down with it, see `EguiDx9::last_error`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EguiDx9Error {
+    /// what we were trying to do.
+    pub context: &'static str,
+    pub code: HRESULT,
+}
+
+impl EguiDx9Error {
+    pub fn new(context: &'static str, err: windows::core::Error) -> Self {
+        Self {
+            context,
+            code: err.code(),
+        }
+    }
+}
+
+impl fmt::Display for EguiDx9Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {:#010X} ({})",
+            self.context,
+            self.code.0,
+            describe_hresult(self.code)
+        )
+    }
+}
+
+impl std::error::Error for EguiDx9Error {}
+
+/// a readable name for the HRESULTs d3d9 commonly fails with.
+pub fn describe_hresult(hr: HRESULT) -> &'static str {
+    match hr.0 as u32 {
+        0x8876017C => "D3DERR_OUTOFVIDEOMEMORY: out of video memory",
+        0x8876086C => "D3DERR_INVALIDCALL: invalid call, a parameter or the device state is wrong",
+        0x88760868 => "D3DERR_DEVICELOST: the device is lost",
+        0x88760869 => "D3DERR_DEVICENOTRESET: the device is lost but can be reset",
+        0x8876086A => "D3DERR_NOTAVAILABLE: not supported by this device",
+        0x8876021C => "D3DERR_WASSTILLDRAWING: the GPU is still using the resource",
+        0x88760827 => "D3DERR_DRIVERINTERNALERROR: internal driver error",
+        0x88760870 => "D3DERR_DEVICEREMOVED: the device was removed",
+        0x8007000E => "E_OUTOFMEMORY: out of system memory",
+        0x80004005 => "E_FAIL: unspecified failure",
+        _ => "unknown error",
+    }
+}
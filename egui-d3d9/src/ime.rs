@@ -0,0 +1,82 @@
+use egui::Pos2;
+use windows::Win32::{
+    Foundation::{HWND, POINT, RECT},
+    UI::Input::Ime::{
+        ImmGetCompositionStringW, ImmGetContext, ImmReleaseContext, ImmSetCandidateWindow,
+        ImmSetCompositionWindow, CANDIDATEFORM, CFS_CANDIDATEPOS, CFS_POINT, COMPOSITIONFORM,
+        GCS_COMPSTR,
+    },
+};
+
+/// moves the IME composition and candidate windows to `pos`, in client coordinates.
+/// without this they end up wherever the IME feels like, usually the corner of the window.
+pub fn set_composition_pos(hwnd: HWND, pos: Pos2) {
+    let point = POINT {
+        x: pos.x as _,
+        y: pos.y as _,
+    };
+
+    unsafe {
+        let himc = ImmGetContext(hwnd);
+
+        // the window doesn't have an input context, e.g. because IME is disabled for it.
+        if himc.0 == 0 {
+            return;
+        }
+
+        ImmSetCompositionWindow(
+            himc,
+            &COMPOSITIONFORM {
+                dwStyle: CFS_POINT,
+                ptCurrentPos: point,
+                rcArea: RECT::default(),
+            },
+        );
+
+        ImmSetCandidateWindow(
+            himc,
+            &CANDIDATEFORM {
+                dwIndex: 0,
+                dwStyle: CFS_CANDIDATEPOS,
+                ptCurrentPos: point,
+                rcArea: RECT::default(),
+            },
+        );
+
+        ImmReleaseContext(hwnd, himc);
+    }
+}
+
+/// the string currently being composed (the preedit), or `None` if the window has no input context.
+pub fn composition_string(hwnd: HWND) -> Option<String> {
+    unsafe {
+        let himc = ImmGetContext(hwnd);
+
+        if himc.0 == 0 {
+            return None;
+        }
+
+        // the first call only asks for the size, in bytes.
+        let len = ImmGetCompositionStringW(himc, GCS_COMPSTR, None, 0);
+
+        let text = if len > 0 {
+            let mut buf = vec![0u16; len as usize / 2];
+
+            let len = ImmGetCompositionStringW(
+                himc,
+                GCS_COMPSTR,
+                Some(buf.as_mut_ptr() as _),
+                (buf.len() * 2) as _,
+            );
+
+            buf.truncate(len.max(0) as usize / 2);
+            String::from_utf16_lossy(&buf)
+        } else {
+            String::new()
+        };
+
+        ImmReleaseContext(hwnd, himc);
+
+        Some(text)
+    }
+}
@@ -1,36 +1,125 @@
 #![allow(dead_code)]
-use clipboard::{windows_clipboard::WindowsClipboardContext, ClipboardProvider};
-use egui::{Event, Key, Modifiers, PointerButton, Pos2, RawInput, Rect, Vec2};
+use std::time::{Duration, Instant};
+
+use egui::{CursorIcon, Event, Key, Modifiers, PointerButton, Pos2, RawInput, Rect, Vec2};
 use windows::{
     Wdk::System::SystemInformation::NtQuerySystemTime,
     Win32::{
-        Foundation::{HWND, RECT},
+        Foundation::HWND,
         System::SystemServices::{MK_CONTROL, MK_SHIFT},
         UI::{
             Input::KeyboardAndMouse::{
                 GetAsyncKeyState, VIRTUAL_KEY, VK_BACK, VK_CONTROL, VK_DELETE, VK_DOWN, VK_END,
-                VK_ESCAPE, VK_HOME, VK_INSERT, VK_LEFT, VK_LSHIFT, VK_NEXT, VK_PRIOR, VK_RETURN,
-                VK_RIGHT, VK_SPACE, VK_TAB, VK_UP,
+                VK_ESCAPE, VK_HOME, VK_INSERT, VK_LEFT, VK_MENU, VK_NEXT, VK_PRIOR, VK_RETURN,
+                VK_RIGHT, VK_SHIFT, VK_SPACE, VK_TAB, VK_UP,
             },
             WindowsAndMessaging::{
-                GetClientRect, KF_REPEAT, WHEEL_DELTA, WM_CHAR, WM_KEYDOWN, WM_KEYUP,
-                WM_LBUTTONDBLCLK, WM_LBUTTONDOWN, WM_LBUTTONUP, WM_MBUTTONDBLCLK, WM_MBUTTONDOWN,
-                WM_MBUTTONUP, WM_MOUSEHWHEEL, WM_MOUSEMOVE, WM_MOUSEWHEEL, WM_RBUTTONDBLCLK,
-                WM_RBUTTONDOWN, WM_RBUTTONUP, WM_SYSKEYDOWN, WM_SYSKEYUP, WM_XBUTTONDBLCLK,
-                WM_XBUTTONDOWN, WM_XBUTTONUP, XBUTTON1, XBUTTON2,
+                LoadCursorW, SetCursor, HCURSOR, HTCLIENT, IDC_APPSTARTING, IDC_ARROW, IDC_CROSS,
+                IDC_HAND, IDC_HELP, IDC_IBEAM, IDC_NO, IDC_SIZEALL, IDC_SIZENESW, IDC_SIZENS,
+                IDC_SIZENWSE, IDC_SIZEWE, IDC_WAIT, KF_REPEAT, WHEEL_DELTA, WM_ACTIVATEAPP,
+                WM_CHAR, WM_INPUT, WM_KEYDOWN, WM_KEYUP, WM_KILLFOCUS, WM_LBUTTONDBLCLK,
+                WM_LBUTTONDOWN, WM_LBUTTONUP, WM_MBUTTONDBLCLK, WM_MBUTTONDOWN, WM_MBUTTONUP,
+                WM_MOUSEACTIVATE, WM_MOUSEHWHEEL, WM_MOUSEMOVE, WM_MOUSEWHEEL, WM_RBUTTONDBLCLK,
+                WM_RBUTTONDOWN, WM_RBUTTONUP, WM_SETCURSOR, WM_SETFOCUS, WM_SETTINGCHANGE,
+                WM_SYSKEYDOWN, WM_SYSKEYUP, WM_XBUTTONDBLCLK, WM_XBUTTONDOWN, WM_XBUTTONUP,
+                XBUTTON1, XBUTTON2,
             },
         },
     },
 };
 
+#[cfg(feature = "ime")]
+use windows::Win32::UI::{
+    Input::Ime::GCS_COMPSTR,
+    WindowsAndMessaging::{WM_IME_COMPOSITION, WM_IME_ENDCOMPOSITION, WM_IME_STARTCOMPOSITION},
+};
+
+#[cfg(feature = "touch")]
+use egui::{TouchDeviceId, TouchId, TouchPhase};
+#[cfg(feature = "touch")]
+use windows::Win32::UI::WindowsAndMessaging::{
+    WM_MOUSEFIRST, WM_MOUSELAST, WM_POINTERDOWN, WM_POINTERLEAVE, WM_POINTERUP, WM_POINTERUPDATE,
+};
+
+use crate::{
+    clipman::{Clipboard, WindowsClipboard},
+    state::Orientation,
+};
+
+/// how long the cached OS theme is trusted without a `WM_SETTINGCHANGE`.
+/// reading it means opening the registry, which is too slow to do every frame.
+const THEME_REFRESH: Duration = Duration::from_secs(5);
+
 pub struct InputManager {
     hwnd: HWND,
     events: Vec<Event>,
     modifiers: Option<Modifiers>,
+    /// whether a key is physically held, `is_key_down` outside of tests.
+    key_down: fn(VIRTUAL_KEY) -> bool,
+    orientation: Orientation,
+    flip_y: bool,
+    focused: bool,
+    refocused: bool,
+    clipboard: Box<dyn Clipboard>,
+    intercept_clipboard: bool,
+    wants_keyboard: bool,
+    wants_pointer: bool,
+    cursor_icon: CursorIcon,
+    app_active: bool,
+    raw_mouse: bool,
+    /// where we pretend the pointer is while `raw_mouse` is on, in client coordinates.
+    virtual_pos: Pos2,
+    /// the window's client size as of the last frame, so raw mouse input doesn't have to ask for it.
+    client_size: Vec2,
+    time_from_input: bool,
+    /// when the oldest event in `events` came in, only tracked with `time_from_input`.
+    first_event_time: Option<f64>,
+    /// in egui points, see `EguiDx9::set_input_region`.
+    input_region: Option<Rect>,
+    /// whether the pointer was inside `input_region` as of the last mouse message.
+    pointer_inside: bool,
+    /// the size and pointer scale `collect_input` got last, to map client positions without waiting for it.
+    screen_size: Vec2,
+    pointer_scale: Vec2,
+    /// the OS switched between light and dark mode since `take_theme_change` was last called.
+    theme_changed: bool,
+    /// read again when `WM_SETTINGCHANGE` says it changed, or after `THEME_REFRESH`
+    /// in case that never reaches us, e.g. with the game's window subclassed by someone else.
+    system_dark: Option<bool>,
+    theme_read_at: Instant,
+    /// reads the OS theme, `theme::system_dark_mode` outside of tests.
+    read_theme: fn() -> Option<bool>,
+    /// buttons we saw go down but not back up, and where the pointer last was in client coordinates.
+    held_buttons: Vec<PointerButton>,
+    last_pos: Pos2,
+}
+
+/// the input state `EguiDx9::input_snapshot` captures and `restore_input` puts back.
+/// events that haven't been handed to egui yet aren't part of it.
+#[derive(Clone, Debug)]
+pub struct InputSnapshot {
+    /// `None` if we haven't seen a key or mouse message since the last focus change.
+    pub modifiers: Option<Modifiers>,
+    /// whether the window has keyboard focus.
+    pub focused: bool,
+    /// false while the user is in another application.
+    pub app_active: bool,
+    /// whether the pointer was last inside `set_input_region`.
+    pub pointer_inside: bool,
+    /// the pointer position `set_raw_mouse` accumulates deltas into, in client coordinates.
+    pub virtual_pos: Pos2,
+    /// the buttons we've seen go down but not back up, `reset_input` releases these.
+    pub held_buttons: Vec<PointerButton>,
+    /// where the pointer last was in client coordinates, the held buttons are released there.
+    pub last_pos: Pos2,
+    /// egui's side: where the pointer is and which buttons are down.
+    pub pointer: egui::PointerState,
 }
 
 /// High-level overview of recognized `WndProc` messages.
 #[repr(u8)]
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum InputResult {
     Unknown,
     MouseMove,
@@ -41,6 +130,18 @@ pub enum InputResult {
     Scroll,
     Zoom,
     Key,
+    Focus,
+    Ime,
+    /// a finger or pen on a touch screen, or a mouse message windows made up from one.
+    Touch,
+    /// we set the cursor. return `TRUE` from your `WndProc` without passing this on,
+    /// or the game will set its own right after.
+    SetCursor,
+    /// the window is being activated by a click on egui. return `MA_ACTIVATEANDEAT` from your
+    /// `WndProc` without passing this on, or `MA_NOACTIVATEANDEAT` to not activate the window either.
+    /// the click is then dropped before the game (or egui) sees it.
+    /// clicks on the game itself come as `Focus`, pass those on as usual.
+    EatActivation,
 }
 
 impl InputResult {
@@ -61,125 +162,307 @@ impl InputManager {
             hwnd,
             events: vec![],
             modifiers: None,
+            key_down: is_key_down,
+            orientation: Orientation::default(),
+            flip_y: false,
+            focused: true,
+            refocused: false,
+            clipboard: Box::<WindowsClipboard>::default(),
+            intercept_clipboard: true,
+            wants_keyboard: false,
+            wants_pointer: false,
+            cursor_icon: CursorIcon::Default,
+            app_active: true,
+            raw_mouse: false,
+            virtual_pos: Pos2::ZERO,
+            client_size: Vec2::ZERO,
+            time_from_input: false,
+            first_event_time: None,
+            input_region: None,
+            pointer_inside: true,
+            screen_size: Vec2::ZERO,
+            pointer_scale: Vec2::splat(1.),
+            theme_changed: false,
+            system_dark: crate::theme::system_dark_mode(),
+            theme_read_at: Instant::now(),
+            read_theme: crate::theme::system_dark_mode,
+            held_buttons: vec![],
+            last_pos: Pos2::ZERO,
+        }
+    }
+
+    pub fn set_hwnd(&mut self, hwnd: HWND) {
+        self.hwnd = hwnd;
+    }
+
+    pub fn set_orientation(&mut self, orientation: Orientation) {
+        self.orientation = orientation;
+    }
+
+    pub fn set_flip_y(&mut self, flip_y: bool) {
+        self.flip_y = flip_y;
+    }
+
+    pub fn set_clipboard(&mut self, clipboard: Box<dyn Clipboard>) {
+        self.clipboard = clipboard;
+    }
+
+    pub fn set_intercept_clipboard(&mut self, intercept: bool) {
+        self.intercept_clipboard = intercept;
+    }
+
+    /// whether egui wanted keyboard input as of the last frame.
+    pub fn set_wants_keyboard(&mut self, wants_keyboard: bool) {
+        self.wants_keyboard = wants_keyboard;
+    }
+
+    /// whether egui wanted pointer input as of the last frame.
+    pub fn set_wants_pointer(&mut self, wants_pointer: bool) {
+        self.wants_pointer = wants_pointer;
+    }
+
+    /// the cursor egui asked for in the last frame, applied on `WM_SETCURSOR`.
+    pub fn set_cursor_icon(&mut self, cursor_icon: CursorIcon) {
+        self.cursor_icon = cursor_icon;
+    }
+
+    /// the client size `EguiDx9` got for this frame, what the raw mouse pointer is kept inside of.
+    pub fn set_client_size(&mut self, size: Vec2) {
+        self.client_size = size;
+    }
+
+    /// see `EguiDx9::set_time_from_input`.
+    pub fn set_time_from_input(&mut self, time_from_input: bool) {
+        self.time_from_input = time_from_input;
+    }
+
+    /// see `EguiDx9::set_input_region`.
+    pub fn set_input_region(&mut self, region: Option<Rect>) {
+        self.input_region = region;
+        self.pointer_inside = true;
+    }
+
+    /// see `EguiDx9::set_raw_mouse`.
+    pub fn set_raw_mouse(&mut self, raw_mouse: bool) {
+        if raw_mouse && !self.raw_mouse {
+            if let Err(err) = crate::rawinput::register_mouse(self.hwnd) {
+                warn!("unable to register for raw mouse input: {}", err);
+            }
+
+            // start off in the middle, that's where games keep the cursor they locked.
+            self.virtual_pos = (self.client_size / 2.).to_pos2();
+            self.push_move(self.virtual_pos);
+        }
+
+        self.raw_mouse = raw_mouse;
+    }
+
+    /// false after the user switched to another application, until they come back.
+    pub fn is_app_active(&self) -> bool {
+        self.app_active
+    }
+
+    /// the OS light/dark setting, as of the last `WM_SETTINGCHANGE` about it.
+    pub fn system_dark_mode(&self) -> Option<bool> {
+        self.system_dark
+    }
+
+    /// whether the OS theme changed since the last call.
+    pub fn take_theme_change(&mut self) -> bool {
+        std::mem::take(&mut self.theme_changed)
+    }
+
+    /// whether anything happened since the last `collect_input`.
+    pub fn has_events(&self) -> bool {
+        !self.events.is_empty()
+    }
+
+    /// our half of an `InputSnapshot`, `pointer` is left at its default.
+    pub fn snapshot(&self) -> InputSnapshot {
+        InputSnapshot {
+            modifiers: self.modifiers,
+            focused: self.focused,
+            app_active: self.app_active,
+            pointer_inside: self.pointer_inside,
+            virtual_pos: self.virtual_pos,
+            held_buttons: self.held_buttons.clone(),
+            last_pos: self.last_pos,
+            pointer: Default::default(),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: &InputSnapshot) {
+        self.modifiers = snapshot.modifiers;
+        self.focused = snapshot.focused;
+        self.app_active = snapshot.app_active;
+        self.pointer_inside = snapshot.pointer_inside;
+        self.virtual_pos = snapshot.virtual_pos;
+        self.held_buttons = snapshot.held_buttons.clone();
+        self.last_pos = snapshot.last_pos;
+    }
+
+    /// forgets everything queued or held, releases the buttons egui thinks are down,
+    /// and tells egui the pointer is gone.
+    pub fn reset(&mut self) {
+        self.events.clear();
+
+        for button in std::mem::take(&mut self.held_buttons) {
+            self.events.push(Event::PointerButton {
+                pos: self.last_pos,
+                button,
+                pressed: false,
+                modifiers: Modifiers::NONE,
+            });
+        }
+
+        self.events.push(Event::PointerGone);
+        self.modifiers = None;
+        self.first_event_time = None;
+        // egui's pointer stays wherever we left it, the next click has to move it first.
+        self.refocused = true;
+    }
+
+    /// queues an event for the next frame, as if it came from the window.
+    pub fn push_event(&mut self, event: Event) {
+        self.events.push(event);
+        self.stamp_first_event();
+    }
+
+    fn stamp_first_event(&mut self) {
+        if self.time_from_input && self.first_event_time.is_none() && self.has_events() {
+            self.first_event_time = Some(Self::get_system_time());
+        }
+    }
+
+    /// hands text egui copied or cut over to the clipboard.
+    /// egui reports nothing copied as an empty string, which mustn't clobber what's there.
+    pub fn set_clipboard_text(&mut self, text: String) {
+        if !text.is_empty() {
+            self.clipboard.set(text);
         }
     }
 
     pub fn process(&mut self, umsg: u32, wparam: usize, lparam: isize) -> InputResult {
+        let result = self.process_message(umsg, wparam, lparam);
+        self.stamp_first_event();
+        result
+    }
+
+    fn process_message(&mut self, umsg: u32, wparam: usize, lparam: isize) -> InputResult {
+        // we saw this one as WM_POINTER* already, taking it again would click twice.
+        #[cfg(feature = "touch")]
+        if (WM_MOUSEFIRST..=WM_MOUSELAST).contains(&umsg) && crate::touch::is_promoted_touch() {
+            return InputResult::Touch;
+        }
+
+        if self.is_outside_region(umsg, lparam) {
+            match umsg {
+                // a drag that started inside still has to end for egui, but the game sees it too.
+                WM_LBUTTONUP | WM_RBUTTONUP | WM_MBUTTONUP | WM_XBUTTONUP => {
+                    self.dispatch(umsg, wparam, lparam);
+                }
+                WM_MOUSEMOVE => {
+                    if !matches!(self.events.last(), Some(Event::PointerGone)) {
+                        self.events.push(Event::PointerGone);
+                    }
+                }
+                _ => {}
+            }
+
+            return InputResult::Unknown;
+        }
+
+        self.dispatch(umsg, wparam, lparam)
+    }
+
+    fn dispatch(&mut self, umsg: u32, wparam: usize, lparam: isize) -> InputResult {
         match umsg {
             WM_MOUSEMOVE => {
                 self.alter_modifiers(get_mouse_modifiers(wparam));
 
-                self.events.push(Event::PointerMoved(get_pos(lparam)));
+                // the game keeps warping the cursor back, the virtual one moves on WM_INPUT instead.
+                if !self.raw_mouse {
+                    self.push_move(get_pos(lparam));
+                }
                 InputResult::MouseMove
             }
+            WM_INPUT if self.raw_mouse => match crate::rawinput::mouse_delta(lparam) {
+                Some(delta) => {
+                    self.virtual_pos =
+                        (self.virtual_pos + delta).clamp(Pos2::ZERO, self.client_size.to_pos2());
+                    self.push_move(self.virtual_pos);
+                    InputResult::MouseMove
+                }
+                None => InputResult::Unknown,
+            },
             WM_LBUTTONDOWN | WM_LBUTTONDBLCLK => {
-                let modifiers = get_mouse_modifiers(wparam);
-                self.alter_modifiers(modifiers);
-
-                self.events.push(Event::PointerButton {
-                    pos: get_pos(lparam),
-                    button: PointerButton::Primary,
-                    pressed: true,
-                    modifiers,
-                });
+                self.push_button(wparam, lparam, PointerButton::Primary, true);
                 InputResult::MouseLeft
             }
             WM_LBUTTONUP => {
-                let modifiers = get_mouse_modifiers(wparam);
-                self.alter_modifiers(modifiers);
-
-                self.events.push(Event::PointerButton {
-                    pos: get_pos(lparam),
-                    button: PointerButton::Primary,
-                    pressed: false,
-                    modifiers,
-                });
+                self.push_button(wparam, lparam, PointerButton::Primary, false);
                 InputResult::MouseLeft
             }
             WM_RBUTTONDOWN | WM_RBUTTONDBLCLK => {
-                let modifiers = get_mouse_modifiers(wparam);
-                self.alter_modifiers(modifiers);
-
-                self.events.push(Event::PointerButton {
-                    pos: get_pos(lparam),
-                    button: PointerButton::Secondary,
-                    pressed: true,
-                    modifiers,
-                });
+                self.push_button(wparam, lparam, PointerButton::Secondary, true);
                 InputResult::MouseRight
             }
             WM_RBUTTONUP => {
-                let modifiers = get_mouse_modifiers(wparam);
-                self.alter_modifiers(modifiers);
-
-                self.events.push(Event::PointerButton {
-                    pos: get_pos(lparam),
-                    button: PointerButton::Secondary,
-                    pressed: false,
-                    modifiers,
-                });
+                self.push_button(wparam, lparam, PointerButton::Secondary, false);
                 InputResult::MouseRight
             }
             WM_MBUTTONDOWN | WM_MBUTTONDBLCLK => {
-                let modifiers = get_mouse_modifiers(wparam);
-                self.alter_modifiers(modifiers);
-
-                self.events.push(Event::PointerButton {
-                    pos: get_pos(lparam),
-                    button: PointerButton::Middle,
-                    pressed: true,
-                    modifiers,
-                });
+                self.push_button(wparam, lparam, PointerButton::Middle, true);
                 InputResult::MouseMiddle
             }
             WM_MBUTTONUP => {
-                let modifiers = get_mouse_modifiers(wparam);
-                self.alter_modifiers(modifiers);
-
-                self.events.push(Event::PointerButton {
-                    pos: get_pos(lparam),
-                    button: PointerButton::Middle,
-                    pressed: false,
-                    modifiers,
-                });
+                self.push_button(wparam, lparam, PointerButton::Middle, false);
                 InputResult::MouseMiddle
             }
             WM_XBUTTONDOWN | WM_XBUTTONDBLCLK => {
-                let modifiers = get_mouse_modifiers(wparam);
-                self.alter_modifiers(modifiers);
-
-                self.events.push(Event::PointerButton {
-                    pos: get_pos(lparam),
-                    button: if (wparam as u32) >> 16 & (XBUTTON1 as u32) != 0 {
-                        PointerButton::Extra1
-                    } else if (wparam as u32) >> 16 & (XBUTTON2 as u32) != 0 {
-                        PointerButton::Extra2
-                    } else {
-                        unreachable!()
-                    },
-                    pressed: true,
-                    modifiers,
-                });
+                self.push_button(wparam, lparam, get_xbutton(wparam), true);
                 InputResult::MouseMiddle
             }
             WM_XBUTTONUP => {
-                let modifiers = get_mouse_modifiers(wparam);
-                self.alter_modifiers(modifiers);
-
-                self.events.push(Event::PointerButton {
-                    pos: get_pos(lparam),
-                    button: if (wparam as u32) >> 16 & (XBUTTON1 as u32) != 0 {
-                        PointerButton::Extra1
-                    } else if (wparam as u32) >> 16 & (XBUTTON2 as u32) != 0 {
-                        PointerButton::Extra2
-                    } else {
-                        unreachable!()
-                    },
-                    pressed: false,
-                    modifiers,
-                });
+                self.push_button(wparam, lparam, get_xbutton(wparam), false);
                 InputResult::MouseMiddle
             }
+            WM_SETFOCUS => {
+                self.focused = true;
+                self.refocused = true;
+                InputResult::Focus
+            }
+            WM_KILLFOCUS => {
+                self.focused = false;
+                // whatever is held now will be released without us seeing it.
+                self.modifiers = None;
+                self.events.push(Event::PointerGone);
+                InputResult::Focus
+            }
+            WM_ACTIVATEAPP => {
+                self.app_active = wparam != 0;
+                InputResult::Focus
+            }
+            WM_SETTINGCHANGE => {
+                // broadcast to every top level window, the game still needs to see it.
+                if crate::theme::is_theme_change(lparam) {
+                    self.system_dark = (self.read_theme)();
+                    self.theme_read_at = Instant::now();
+                    self.theme_changed = true;
+                }
+
+                InputResult::Unknown
+            }
+            WM_MOUSEACTIVATE => {
+                // the click that activates us follows this message, unless the host eats it.
+                self.refocused = true;
+
+                if self.wants_pointer {
+                    InputResult::EatActivation
+                } else {
+                    InputResult::Focus
+                }
+            }
             WM_CHAR => {
                 if let Some(ch) = char::from_u32(wparam as _) {
                     if !ch.is_control() {
@@ -188,6 +471,55 @@ impl InputManager {
                 }
                 InputResult::Character
             }
+            #[cfg(feature = "ime")]
+            WM_IME_STARTCOMPOSITION => {
+                self.events.push(Event::CompositionStart);
+                InputResult::Ime
+            }
+            #[cfg(feature = "ime")]
+            WM_IME_COMPOSITION => {
+                // the result string isn't pushed here. DefWindowProc turns it into WM_IME_CHAR
+                // and then WM_CHAR, which we already handle, so it would get typed twice.
+                if lparam as u32 & GCS_COMPSTR.0 != 0 {
+                    if let Some(text) = crate::ime::composition_string(self.hwnd) {
+                        self.events.push(Event::CompositionUpdate(text));
+                    }
+                }
+                InputResult::Ime
+            }
+            #[cfg(feature = "ime")]
+            WM_IME_ENDCOMPOSITION => {
+                // drops whatever preedit is left, e.g. when the composition was cancelled.
+                self.events.push(Event::CompositionEnd(String::new()));
+                InputResult::Ime
+            }
+            #[cfg(feature = "touch")]
+            msg @ (WM_POINTERDOWN | WM_POINTERUPDATE | WM_POINTERUP) => {
+                match crate::touch::touch_point(self.hwnd, wparam) {
+                    Some(touch) => {
+                        self.push_touch(msg, touch);
+                        InputResult::Touch
+                    }
+                    // mice also come through here, but as regular messages too.
+                    None => InputResult::Unknown,
+                }
+            }
+            // a pen that went out of range. fingers already left with their WM_POINTERUP.
+            #[cfg(feature = "touch")]
+            WM_POINTERLEAVE => match crate::touch::touch_point(self.hwnd, wparam) {
+                Some(touch) if touch.pen => {
+                    self.events.push(Event::PointerGone);
+                    InputResult::Touch
+                }
+                _ => InputResult::Unknown,
+            },
+            // only over the client area, so resize borders and such keep their cursors.
+            WM_SETCURSOR if self.wants_pointer && (lparam & 0xFFFF) as u32 == HTCLIENT => {
+                unsafe {
+                    SetCursor(get_cursor(self.cursor_icon));
+                }
+                InputResult::SetCursor
+            }
             WM_MOUSEWHEEL => {
                 self.alter_modifiers(get_mouse_modifiers(wparam));
 
@@ -217,35 +549,40 @@ impl InputManager {
                 }
             }
             msg @ (WM_KEYDOWN | WM_SYSKEYDOWN) => {
-                let modifiers = get_key_modifiers(msg);
+                let modifiers = get_key_modifiers(msg, wparam);
                 self.modifiers = Some(modifiers);
 
                 if let Some(key) = get_key(wparam) {
-                    if key == Key::V && modifiers.ctrl {
-                        if let Some(clipboard) = get_clipboard_text() {
-                            self.events.push(Event::Text(clipboard));
+                    // only take over the shortcuts while a text field has focus,
+                    // otherwise they belong to the game.
+                    if modifiers.ctrl && self.intercept_clipboard && self.wants_keyboard {
+                        match key {
+                            Key::V => {
+                                if let Some(clipboard) = self.clipboard.get() {
+                                    self.events.push(Event::Text(clipboard));
+                                }
+                            }
+                            Key::C => self.events.push(Event::Copy),
+                            Key::X => self.events.push(Event::Cut),
+                            _ => {}
                         }
                     }
 
-                    if key == Key::C && modifiers.ctrl {
-                        self.events.push(Event::Copy);
-                    }
-
-                    if key == Key::X && modifiers.ctrl {
-                        self.events.push(Event::Cut);
-                    }
+                    // the flags live in the high word. the low word counts auto-repeats folded
+                    // into this message, but one event per message is plenty for egui.
+                    let was_down = (lparam >> 16) & (KF_REPEAT as isize) != 0;
 
                     self.events.push(Event::Key {
                         pressed: true,
                         modifiers,
                         key,
-                        repeat: lparam & (KF_REPEAT as isize) > 0,
+                        repeat: was_down,
                     });
                 }
                 InputResult::Key
             }
             msg @ (WM_KEYUP | WM_SYSKEYUP) => {
-                let modifiers = get_key_modifiers(msg);
+                let modifiers = get_key_modifiers(msg, wparam);
                 self.modifiers = Some(modifiers);
 
                 if let Some(key) = get_key(wparam) {
@@ -262,24 +599,221 @@ impl InputManager {
         }
     }
 
+    /// egui only cares about where the pointer ended up, so back-to-back moves collapse into the last one.
+    /// anything else in between (a click, a scroll) keeps the moves around it apart.
+    fn push_move(&mut self, pos: Pos2) {
+        self.last_pos = pos;
+
+        if let Some(Event::PointerMoved(last)) = self.events.last_mut() {
+            *last = pos;
+        } else {
+            self.events.push(Event::PointerMoved(pos));
+        }
+    }
+
+    /// whether this is a mouse message that belongs to the game because of `input_region`.
+    /// wheel messages carry screen coordinates, so they go by where the pointer last was.
+    fn is_outside_region(&mut self, umsg: u32, lparam: isize) -> bool {
+        let Some(region) = self.input_region else {
+            return false;
+        };
+
+        match umsg {
+            WM_MOUSEWHEEL | WM_MOUSEHWHEEL => !self.pointer_inside,
+            WM_MOUSEMOVE | WM_LBUTTONDOWN | WM_LBUTTONDBLCLK | WM_LBUTTONUP | WM_RBUTTONDOWN
+            | WM_RBUTTONDBLCLK | WM_RBUTTONUP | WM_MBUTTONDOWN | WM_MBUTTONDBLCLK
+            | WM_MBUTTONUP | WM_XBUTTONDOWN | WM_XBUTTONDBLCLK | WM_XBUTTONUP => {
+                self.pointer_inside = region.contains(self.to_egui(self.pointer_pos(lparam)));
+                !self.pointer_inside
+            }
+            _ => false,
+        }
+    }
+
+    /// where a mouse message happened, in client coordinates.
+    fn pointer_pos(&self, lparam: isize) -> Pos2 {
+        if self.raw_mouse {
+            self.virtual_pos
+        } else {
+            get_pos(lparam)
+        }
+    }
+
+    /// takes a client position into egui's space, as of the last `collect_input`.
+    fn to_egui(&self, pos: Pos2) -> Pos2 {
+        let (width, height) = (self.screen_size.x, self.screen_size.y);
+        let (_, h) = self.orientation.logical_size(width, height);
+
+        let scaled = Pos2::new(pos.x * self.pointer_scale.x, pos.y * self.pointer_scale.y);
+        let mut pos = self.orientation.to_logical(scaled, width, height);
+
+        if self.flip_y {
+            pos.y = h - pos.y;
+        }
+
+        pos
+    }
+
+    fn push_button(&mut self, wparam: usize, lparam: isize, button: PointerButton, pressed: bool) {
+        let modifiers = get_mouse_modifiers(wparam);
+        self.alter_modifiers(modifiers);
+
+        let pos = self.pointer_pos(lparam);
+
+        // we didn't get any moves while unfocused, so egui's pointer is wherever we left it.
+        // move it to the click first, so the first click after refocusing lands where it was made
+        // and not on whatever was hovered when focus was lost.
+        if pressed && std::mem::take(&mut self.refocused) {
+            self.push_move(pos);
+        }
+
+        self.last_pos = pos;
+        self.held_buttons.retain(|&held| held != button);
+        if pressed {
+            self.held_buttons.push(button);
+        }
+
+        self.events.push(Event::PointerButton {
+            pos,
+            button,
+            pressed,
+            modifiers,
+        });
+    }
+
+    /// egui's widgets only look at the pointer, so the primary finger or the pen drives it like a mouse would.
+    /// egui works out pinch zoom and such from the touch events on its own.
+    #[cfg(feature = "touch")]
+    fn push_touch(&mut self, msg: u32, touch: crate::touch::TouchPoint) {
+        let phase = match msg {
+            _ if touch.canceled => TouchPhase::Cancel,
+            WM_POINTERDOWN => TouchPhase::Start,
+            WM_POINTERUP => TouchPhase::End,
+            _ => TouchPhase::Move,
+        };
+
+        // a hovering pen is just a pointer moving around, only contact is a touch.
+        if phase == TouchPhase::Move && !touch.in_contact {
+            self.push_move(touch.pos);
+            return;
+        }
+
+        self.events.push(Event::Touch {
+            device_id: TouchDeviceId(touch.device),
+            id: TouchId(touch.id),
+            phase,
+            pos: touch.pos,
+            force: touch.force,
+        });
+
+        if !touch.primary {
+            return;
+        }
+
+        let modifiers = self.modifiers.unwrap_or_default();
+
+        match phase {
+            TouchPhase::Start => {
+                self.push_move(touch.pos);
+                self.events.push(Event::PointerButton {
+                    pos: touch.pos,
+                    button: PointerButton::Primary,
+                    pressed: true,
+                    modifiers,
+                });
+            }
+            TouchPhase::Move => self.push_move(touch.pos),
+            TouchPhase::End | TouchPhase::Cancel => {
+                self.events.push(Event::PointerButton {
+                    pos: touch.pos,
+                    button: PointerButton::Primary,
+                    pressed: false,
+                    modifiers,
+                });
+                // a lifted finger doesn't hover anything, a lifted pen usually still does.
+                if !touch.pen {
+                    self.events.push(Event::PointerGone);
+                }
+            }
+        }
+    }
+
     fn alter_modifiers(&mut self, new: Modifiers) {
         if let Some(old) = self.modifiers.as_mut() {
             *old = new;
         }
     }
 
-    pub fn collect_input(&mut self) -> RawInput {
+    /// Modifiers can get stuck if their key-up arrives while we're not receiving messages
+    /// (e.g. released while the window is unfocused). Clear any cached modifier the OS
+    /// reports as released. We never set one here, a held modifier is picked up by `process`.
+    fn reconcile_modifiers(&mut self) {
+        if let Some(modifiers) = self.modifiers.as_mut() {
+            release_stale_modifiers(modifiers, self.key_down);
+        }
+    }
+
+    fn refresh_theme(&mut self) {
+        if self.theme_read_at.elapsed() < THEME_REFRESH {
+            return;
+        }
+
+        let dark = (self.read_theme)();
+        self.theme_read_at = Instant::now();
+
+        if dark != self.system_dark {
+            self.system_dark = dark;
+            self.theme_changed = true;
+        }
+    }
+
+    /// `pointer_scale` takes client coordinates to `width`/`height`, for when those aren't the client size.
+    pub fn collect_input(
+        &mut self,
+        width: f32,
+        height: f32,
+        pixels_per_point: f32,
+        pointer_scale: Vec2,
+    ) -> RawInput {
+        self.reconcile_modifiers();
+        self.refresh_theme();
+
+        let mut events = std::mem::take(&mut self.events);
+
+        // without events this frame, the frame itself is the only time there is.
+        let time = self
+            .first_event_time
+            .take()
+            .unwrap_or_else(Self::get_system_time);
+        let (w, h) = self.orientation.logical_size(width, height);
+
+        self.screen_size = Vec2::new(width, height);
+        self.pointer_scale = pointer_scale;
+
+        // positions are recorded in client space, move them into egui's space.
+        if self.orientation != Orientation::Deg0 || self.flip_y || pointer_scale != Vec2::splat(1.)
+        {
+            events.iter_mut().for_each(|event| {
+                if let Event::PointerMoved(pos)
+                | Event::PointerButton { pos, .. }
+                | Event::Touch { pos, .. } = event
+                {
+                    *pos = self.to_egui(*pos);
+                }
+            });
+        }
+
         RawInput {
             modifiers: self.modifiers.unwrap_or_default(),
-            events: std::mem::take(&mut self.events),
-            screen_rect: Some(self.get_screen_rect()),
-            time: Some(Self::get_system_time()),
-            pixels_per_point: Some(1.),
+            events,
+            screen_rect: Some(Rect::from_min_size(Pos2::ZERO, Vec2::new(w, h))),
+            time: Some(time),
+            pixels_per_point: Some(pixels_per_point),
             max_texture_side: None,
             predicted_dt: 1. / 60.,
             hovered_files: vec![],
             dropped_files: vec![],
-            focused: true,
+            focused: self.focused,
         }
     }
 
@@ -299,30 +833,41 @@ impl InputManager {
         // past since 1st Jan, 1601.
         (time as f64) / 10_000_000.
     }
+}
 
-    #[inline]
-    pub fn get_screen_size(&self) -> Pos2 {
-        let mut rect = RECT::default();
-        unsafe {
-            expect!(
-                GetClientRect(self.hwnd, &mut rect),
-                "Failed to GetClientRect()"
-            );
+/// a null cursor hides it.
+fn get_cursor(icon: CursorIcon) -> HCURSOR {
+    let name = match icon {
+        CursorIcon::None => return HCURSOR::default(),
+        CursorIcon::PointingHand => IDC_HAND,
+        CursorIcon::Text | CursorIcon::VerticalText => IDC_IBEAM,
+        CursorIcon::Wait => IDC_WAIT,
+        CursorIcon::Progress => IDC_APPSTARTING,
+        CursorIcon::Help => IDC_HELP,
+        CursorIcon::Crosshair => IDC_CROSS,
+        CursorIcon::NotAllowed | CursorIcon::NoDrop => IDC_NO,
+        CursorIcon::Move | CursorIcon::AllScroll | CursorIcon::Grab | CursorIcon::Grabbing => {
+            IDC_SIZEALL
         }
-
-        Pos2::new(
-            (rect.right - rect.left) as f32,
-            (rect.bottom - rect.top) as f32,
-        )
-    }
-
-    #[inline]
-    pub fn get_screen_rect(&self) -> Rect {
-        Rect {
-            min: Pos2::ZERO,
-            max: self.get_screen_size(),
+        CursorIcon::ResizeHorizontal
+        | CursorIcon::ResizeEast
+        | CursorIcon::ResizeWest
+        | CursorIcon::ResizeColumn => IDC_SIZEWE,
+        CursorIcon::ResizeVertical
+        | CursorIcon::ResizeNorth
+        | CursorIcon::ResizeSouth
+        | CursorIcon::ResizeRow => IDC_SIZENS,
+        CursorIcon::ResizeNeSw | CursorIcon::ResizeNorthEast | CursorIcon::ResizeSouthWest => {
+            IDC_SIZENESW
         }
-    }
+        CursorIcon::ResizeNwSe | CursorIcon::ResizeNorthWest | CursorIcon::ResizeSouthEast => {
+            IDC_SIZENWSE
+        }
+        // windows has no match for the rest.
+        _ => IDC_ARROW,
+    };
+
+    unsafe { LoadCursorW(None, name).unwrap_or_default() }
 }
 
 fn get_pos(lparam: isize) -> Pos2 {
@@ -332,9 +877,36 @@ fn get_pos(lparam: isize) -> Pos2 {
     Pos2::new(x, y)
 }
 
+/// clears every modifier in `modifiers` that `key_down` says isn't held anymore.
+fn release_stale_modifiers(modifiers: &mut Modifiers, key_down: fn(VIRTUAL_KEY) -> bool) {
+    if modifiers.ctrl && !key_down(VK_CONTROL) {
+        modifiers.ctrl = false;
+        modifiers.command = false;
+    }
+
+    if modifiers.shift && !key_down(VK_SHIFT) {
+        modifiers.shift = false;
+    }
+
+    if modifiers.alt && !key_down(VK_MENU) {
+        modifiers.alt = false;
+    }
+}
+
+fn get_xbutton(wparam: usize) -> PointerButton {
+    if (wparam as u32) >> 16 & (XBUTTON1 as u32) != 0 {
+        PointerButton::Extra1
+    } else if (wparam as u32) >> 16 & (XBUTTON2 as u32) != 0 {
+        PointerButton::Extra2
+    } else {
+        unreachable!()
+    }
+}
+
 fn get_mouse_modifiers(wparam: usize) -> Modifiers {
     Modifiers {
-        alt: false,
+        // mouse messages don't carry alt, so ask for it.
+        alt: is_key_down(VK_MENU),
         ctrl: (wparam & MK_CONTROL.0 as usize) != 0,
         shift: (wparam & MK_SHIFT.0 as usize) != 0,
         mac_cmd: false,
@@ -342,12 +914,28 @@ fn get_mouse_modifiers(wparam: usize) -> Modifiers {
     }
 }
 
-fn get_key_modifiers(msg: u32) -> Modifiers {
-    let ctrl = unsafe { GetAsyncKeyState(VK_CONTROL.0 as _) != 0 };
-    let shift = unsafe { GetAsyncKeyState(VK_LSHIFT.0 as _) != 0 };
+fn get_key_modifiers(msg: u32, wparam: usize) -> Modifiers {
+    let vk = VIRTUAL_KEY(wparam as u16);
+    let pressed = matches!(msg, WM_KEYDOWN | WM_SYSKEYDOWN);
+
+    // for the modifier that sent this message, trust the message over the async state,
+    // so pressing e.g. Ctrl on its own is seen right away by the next click.
+    let held = |modifier: VIRTUAL_KEY| {
+        if vk == modifier {
+            pressed
+        } else {
+            is_key_down(modifier)
+        }
+    };
+
+    let ctrl = held(VK_CONTROL);
+    let shift = held(VK_SHIFT);
+    // not `msg == WM_SYSKEYDOWN`: F10 is a sys key without alt,
+    // and keys pressed with alt held can still arrive as WM_KEYDOWN.
+    let alt = held(VK_MENU);
 
     Modifiers {
-        alt: msg == WM_SYSKEYDOWN,
+        alt,
         mac_cmd: false,
         command: ctrl,
         shift,
@@ -355,6 +943,12 @@ fn get_key_modifiers(msg: u32) -> Modifiers {
     }
 }
 
+#[inline]
+fn is_key_down(vk: VIRTUAL_KEY) -> bool {
+    // the most significant bit is set while the key is physically held.
+    (unsafe { GetAsyncKeyState(vk.0 as _) } as u16 & 0x8000) != 0
+}
+
 fn get_key(wparam: usize) -> Option<Key> {
     match wparam {
         0x30..=0x39 => unsafe { Some(std::mem::transmute::<_, Key>(wparam as u8 - 0x1F)) },
@@ -381,6 +975,427 @@ fn get_key(wparam: usize) -> Option<Key> {
     }
 }
 
-fn get_clipboard_text() -> Option<String> {
-    WindowsClipboardContext.get_contents().ok()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::pos2;
+    use std::{
+        cell::{Cell, RefCell},
+        rc::Rc,
+    };
+    use windows::Win32::System::SystemServices::MK_LBUTTON;
+
+    fn nothing_held(_: VIRTUAL_KEY) -> bool {
+        false
+    }
+
+    fn ctrl_held(vk: VIRTUAL_KEY) -> bool {
+        vk == VK_CONTROL
+    }
+
+    fn manager(key_down: fn(VIRTUAL_KEY) -> bool) -> InputManager {
+        let mut input = InputManager::new(HWND::default());
+        input.key_down = key_down;
+        input
+    }
+
+    #[test]
+    fn modifiers_released_elsewhere_are_cleared() {
+        let all = Modifiers {
+            alt: true,
+            ctrl: true,
+            shift: true,
+            mac_cmd: false,
+            command: true,
+        };
+
+        let mut modifiers = all;
+        release_stale_modifiers(&mut modifiers, nothing_held);
+        assert_eq!(modifiers, Modifiers::NONE);
+
+        // one that's really still held stays.
+        let mut modifiers = all;
+        release_stale_modifiers(&mut modifiers, ctrl_held);
+        assert_eq!(
+            modifiers,
+            Modifiers {
+                ctrl: true,
+                command: true,
+                ..Modifiers::NONE
+            }
+        );
+    }
+
+    #[test]
+    fn ctrl_released_while_unfocused_clears() {
+        let mut input = manager(ctrl_held);
+        input.modifiers = Some(Modifiers {
+            ctrl: true,
+            command: true,
+            ..Modifiers::NONE
+        });
+
+        input.reconcile_modifiers();
+        assert!(input.modifiers.is_some_and(|modifiers| modifiers.ctrl));
+
+        // focus goes elsewhere and Ctrl is let go there, we never see the WM_KEYUP.
+        input.key_down = nothing_held;
+        input.reconcile_modifiers();
+        assert!(input.modifiers.is_some_and(|modifiers| !modifiers.ctrl));
+    }
+
+    #[test]
+    fn first_click_after_refocus_is_delivered_and_marked() {
+        let mut input = manager(nothing_held);
+        input.process(WM_KILLFOCUS, 0, 0);
+        input.process(WM_SETFOCUS, 0, 0);
+        input.events.clear();
+
+        let lparam = 40 << 16 | 30;
+        assert!(matches!(
+            input.process(WM_LBUTTONDOWN, 0, lparam),
+            InputResult::MouseLeft
+        ));
+        assert_eq!(
+            input.events,
+            [
+                Event::PointerMoved(pos2(30., 40.)),
+                Event::PointerButton {
+                    pos: pos2(30., 40.),
+                    button: PointerButton::Primary,
+                    pressed: true,
+                    modifiers: Modifiers::NONE,
+                },
+            ]
+        );
+
+        // only the first one, later clicks go through as-is.
+        input.events.clear();
+        input.process(WM_LBUTTONUP, 0, lparam);
+        input.process(WM_LBUTTONDOWN, 0, lparam);
+        assert!(!input
+            .events
+            .iter()
+            .any(|event| matches!(event, Event::PointerMoved(_))));
+    }
+
+    fn events(input: &mut InputManager) -> Vec<Event> {
+        input.collect_input(800., 600., 1., Vec2::splat(1.)).events
+    }
+
+    fn copies(input: &mut InputManager) -> usize {
+        input.process(WM_KEYDOWN, b'C' as _, 1);
+        input.process(WM_KEYUP, b'C' as _, 1);
+
+        events(input)
+            .iter()
+            .filter(|event| matches!(event, Event::Copy))
+            .count()
+    }
+
+    #[test]
+    fn ctrl_c_is_only_taken_while_egui_wants_the_keyboard() {
+        let mut input = manager(ctrl_held);
+
+        // no text field focused, the shortcut belongs to the game.
+        assert_eq!(copies(&mut input), 0);
+
+        input.set_wants_keyboard(true);
+        assert_eq!(copies(&mut input), 1);
+
+        input.set_intercept_clipboard(false);
+        assert_eq!(copies(&mut input), 0);
+
+        // and without Ctrl it's just a C.
+        input.set_intercept_clipboard(true);
+        input.key_down = nothing_held;
+        assert_eq!(copies(&mut input), 0);
+    }
+
+    fn key_repeats(input: &mut InputManager, lparam: isize) -> Vec<bool> {
+        input.process(WM_KEYDOWN, b'A' as _, lparam);
+
+        events(input)
+            .iter()
+            .filter_map(|event| match event {
+                Event::Key {
+                    key: Key::A,
+                    pressed: true,
+                    repeat,
+                    ..
+                } => Some(*repeat),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keydown_repeat_flag() {
+        let mut input = manager(nothing_held);
+        let previously_down = 1 << 30;
+
+        assert_eq!(key_repeats(&mut input, 1), [false]);
+        assert_eq!(key_repeats(&mut input, 1 | previously_down), [true]);
+
+        // a folded repeat count still makes one event, not one per repeat.
+        assert_eq!(key_repeats(&mut input, 0xFFFF | previously_down), [true]);
+    }
+
+    fn lparam(x: i16, y: i16) -> isize {
+        (x as u16 as isize) | (y as u16 as isize) << 16
+    }
+
+    #[test]
+    fn ctrl_then_click() {
+        let mut input = manager(ctrl_held);
+
+        // the bare key message alone has to be enough, before any click reports MK_CONTROL.
+        input.process(WM_KEYDOWN, VK_CONTROL.0 as _, 1);
+        assert!(input.modifiers.is_some_and(|modifiers| modifiers.ctrl));
+
+        input.process(
+            WM_LBUTTONDOWN,
+            (MK_LBUTTON.0 | MK_CONTROL.0) as _,
+            lparam(10, 20),
+        );
+
+        let input = input.collect_input(800., 600., 1., Vec2::splat(1.));
+
+        assert!(input.modifiers.ctrl);
+        assert!(input.events.iter().any(|event| matches!(
+            event,
+            Event::PointerButton {
+                button: PointerButton::Primary,
+                pressed: true,
+                modifiers,
+                ..
+            } if modifiers.ctrl
+        )));
+    }
+
+    #[test]
+    fn moves_between_clicks_coalesce() {
+        let mut input = manager(nothing_held);
+
+        (0..100).for_each(|i| {
+            input.process(WM_MOUSEMOVE, 0, lparam(i, i * 2));
+        });
+        input.process(WM_LBUTTONDOWN, MK_LBUTTON.0 as _, lparam(99, 198));
+
+        let events = events(&mut input);
+
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0], Event::PointerMoved(pos) if pos == Pos2::new(99., 198.)));
+        assert!(matches!(
+            events[1],
+            Event::PointerButton {
+                button: PointerButton::Primary,
+                pressed: true,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn input_region() {
+        let mut input = manager(nothing_held);
+        input.set_input_region(Some(Rect::from_min_max(
+            Pos2::new(10., 10.),
+            Pos2::new(110., 110.),
+        )));
+
+        let mut click = |x, y| {
+            let result = input.process(WM_LBUTTONDOWN, MK_LBUTTON.0 as _, lparam(x, y));
+            input.process(WM_LBUTTONUP, 0, lparam(x, y));
+            result
+        };
+
+        assert_eq!(click(50, 50), InputResult::MouseLeft);
+        // the edges count as inside.
+        assert_eq!(click(10, 10), InputResult::MouseLeft);
+        assert_eq!(click(110, 110), InputResult::MouseLeft);
+
+        assert_eq!(click(111, 50), InputResult::Unknown);
+        assert_eq!(click(50, 9), InputResult::Unknown);
+        assert_eq!(click(500, 500), InputResult::Unknown);
+
+        // egui only saw the presses inside.
+        let presses = events(&mut input)
+            .iter()
+            .filter(|event| matches!(event, Event::PointerButton { pressed: true, .. }))
+            .count();
+        assert_eq!(presses, 3);
+
+        // leaving the region takes the pointer away from egui.
+        assert_eq!(
+            input.process(WM_MOUSEMOVE, 0, lparam(50, 50)),
+            InputResult::MouseMove
+        );
+        assert_eq!(
+            input.process(WM_MOUSEMOVE, 0, lparam(200, 50)),
+            InputResult::Unknown
+        );
+        assert!(matches!(
+            events(&mut input).last(),
+            Some(Event::PointerGone)
+        ));
+    }
+
+    thread_local! {
+        static THEME_READS: Cell<usize> = Cell::new(0);
+    }
+
+    fn counted_theme() -> Option<bool> {
+        THEME_READS.with(|reads| reads.set(reads.get() + 1));
+        Some(true)
+    }
+
+    fn theme_reads() -> usize {
+        THEME_READS.with(Cell::get)
+    }
+
+    /// a `WM_SETTINGCHANGE` lparam, the name of the setting area that changed.
+    fn setting_area(name: &str) -> Vec<u16> {
+        name.encode_utf16().chain([0]).collect()
+    }
+
+    #[test]
+    fn theme_changes_are_read_again() {
+        let mut input = manager(nothing_held);
+        input.read_theme = counted_theme;
+
+        let other = setting_area("Policy");
+        input.process(WM_SETTINGCHANGE, 0, other.as_ptr() as _);
+        assert_eq!(theme_reads(), 0);
+        assert!(!input.take_theme_change());
+
+        let theme = setting_area("ImmersiveColorSet");
+        input.process(WM_SETTINGCHANGE, 0, theme.as_ptr() as _);
+        assert_eq!(theme_reads(), 1);
+        assert!(input.take_theme_change());
+        assert_eq!(input.system_dark_mode(), Some(true));
+    }
+
+    #[test]
+    fn theme_is_not_read_every_frame() {
+        let mut input = manager(nothing_held);
+        input.read_theme = counted_theme;
+        // as if it was last read a while ago.
+        input.theme_read_at = Instant::now() - THEME_REFRESH;
+
+        for _ in 0..100 {
+            let _ = input.collect_input(800., 600., 1., Vec2::splat(1.));
+        }
+        assert_eq!(theme_reads(), 1);
+        assert_eq!(input.system_dark_mode(), Some(true));
+    }
+
+    struct RecordingClipboard(Rc<RefCell<Vec<String>>>);
+
+    impl Clipboard for RecordingClipboard {
+        fn get(&mut self) -> Option<String> {
+            None
+        }
+
+        fn set(&mut self, text: String) {
+            self.0.borrow_mut().push(text);
+        }
+    }
+
+    #[test]
+    fn ctrl_c_without_a_focused_field_writes_nothing() {
+        let writes = Rc::new(RefCell::new(vec![]));
+        let mut input = manager(ctrl_held);
+        input.set_clipboard(Box::new(RecordingClipboard(writes.clone())));
+
+        let ctx = egui::Context::default();
+
+        // once with egui not wanting the keyboard, once wanting it but with nothing selected.
+        for wants_keyboard in [false, true] {
+            input.set_wants_keyboard(wants_keyboard);
+            input.process(WM_KEYDOWN, b'C' as _, 1);
+
+            let output = ctx.run(input.collect_input(800., 600., 1., Vec2::splat(1.)), |_| {});
+            input.set_clipboard_text(output.platform_output.copied_text);
+        }
+
+        assert!(writes.borrow().is_empty());
+
+        input.set_clipboard_text("copied".to_owned());
+        assert_eq!(*writes.borrow(), ["copied"]);
+    }
+
+    #[test]
+    fn reset_releases_everything() {
+        let mut input = manager(ctrl_held);
+        let ctx = egui::Context::default();
+        let frame = |input: RawInput| {
+            let _ = ctx.run(input, |_| {});
+            ctx.input(|input| (input.pointer.any_down(), input.modifiers.ctrl))
+        };
+
+        input.process(WM_KEYDOWN, VK_CONTROL.0 as _, 1);
+        input.process(
+            WM_LBUTTONDOWN,
+            (MK_LBUTTON.0 | MK_CONTROL.0) as _,
+            lparam(10, 20),
+        );
+        assert_eq!(
+            frame(input.collect_input(800., 600., 1., Vec2::splat(1.))),
+            (true, true)
+        );
+
+        // typed while the host's dialog was coming up, never handed to egui.
+        input.process(WM_CHAR, 'x' as _, 0);
+        input.reset();
+
+        let raw = input.collect_input(800., 600., 1., Vec2::splat(1.));
+        assert!(!raw
+            .events
+            .iter()
+            .any(|event| matches!(event, Event::Text(_))));
+        assert!(matches!(raw.events.last(), Some(Event::PointerGone)));
+        assert_eq!(raw.modifiers, Modifiers::NONE);
+
+        assert_eq!(frame(raw), (false, false));
+    }
+
+    #[test]
+    fn snapshots_put_back_what_reset_forgot() {
+        let mut input = manager(ctrl_held);
+
+        input.process(WM_SETFOCUS, 0, 0);
+        input.process(WM_KEYDOWN, VK_CONTROL.0 as _, 1);
+        input.process(
+            WM_LBUTTONDOWN,
+            (MK_LBUTTON.0 | MK_CONTROL.0) as _,
+            lparam(10, 20),
+        );
+        let _ = input.collect_input(800., 600., 1., Vec2::splat(1.));
+
+        let snapshot = input.snapshot();
+        assert!(snapshot.focused);
+        assert_eq!(snapshot.held_buttons, [PointerButton::Primary]);
+        assert_eq!(snapshot.last_pos, pos2(10., 20.));
+
+        input.reset();
+        input.restore(&snapshot);
+
+        let restored = input.snapshot();
+        assert_eq!(restored.modifiers, snapshot.modifiers);
+        assert_eq!(restored.focused, snapshot.focused);
+        assert_eq!(restored.held_buttons, snapshot.held_buttons);
+
+        // and the button that came back can still be released.
+        input.reset();
+        let raw = input.collect_input(800., 600., 1., Vec2::splat(1.));
+        assert!(raw.events.iter().any(|event| matches!(
+            event,
+            Event::PointerButton {
+                button: PointerButton::Primary,
+                pressed: false,
+                ..
+            }
+        )));
+    }
 }
@@ -0,0 +1,144 @@
+use std::sync::Arc;
+
+use egui::{
+    epaint::{ClippedShape, PaintCallback},
+    Area, Context, Id, LayerId, Order, Pos2, Rect, Shape,
+};
+
+/// every `Order`, back to front. egui's own list isn't public.
+pub const ORDERS: [Order; 6] = [
+    Order::Background,
+    Order::PanelResizeLine,
+    Order::Middle,
+    Order::Foreground,
+    Order::Tooltip,
+    Order::Debug,
+];
+
+/// painted first in each `Order`, so we can tell where it starts in `FullOutput::shapes`,
+/// which doesn't say what layer a shape came from.
+struct OrderMarker(Order);
+
+fn marker_layer(order: Order) -> LayerId {
+    LayerId::new(order, Id::new(("egui-d3d9 order marker", order)))
+}
+
+/// has to run at the start of every frame, before any UI.
+pub fn mark_orders(ctx: &Context) {
+    for order in ORDERS {
+        let marker = marker_layer(order);
+
+        // an area is the only public way to put a layer into egui's paint order.
+        Area::new(marker.id)
+            .order(order)
+            .interactable(false)
+            .fixed_pos(Pos2::ZERO)
+            .show(ctx, |_| {});
+
+        // egui paints areas in the order they first showed up, so the marker is only first
+        // if nothing of its order came before it, which isn't the case with restored memory.
+        // raising everything else puts it all above the marker, in the same order as before.
+        let layers: Vec<LayerId> = ctx.memory(|mem| {
+            mem.layer_ids()
+                .filter(|layer| layer.order == order)
+                .collect()
+        });
+
+        if layers.first() != Some(&marker) {
+            layers
+                .into_iter()
+                .filter(|layer| *layer != marker)
+                .for_each(|layer| ctx.move_to_top(layer));
+        }
+
+        // the area's own ui is invisible on its first frame, paint on the layer directly.
+        ctx.layer_painter(marker).add(PaintCallback {
+            rect: Rect::NOTHING,
+            callback: Arc::new(OrderMarker(order)),
+        });
+    }
+}
+
+fn marker_order(shape: &ClippedShape) -> Option<Order> {
+    match &shape.shape {
+        Shape::Callback(callback) => callback
+            .callback
+            .downcast_ref::<OrderMarker>()
+            .map(|marker| marker.0),
+        _ => None,
+    }
+}
+
+/// groups the shapes of a frame that ran `mark_orders` by their `Order`, back to front.
+/// the markers are dropped, and so are orders that painted nothing.
+pub fn split_by_order(shapes: Vec<ClippedShape>) -> Vec<(Order, Vec<ClippedShape>)> {
+    let mut groups: Vec<(Order, Vec<ClippedShape>)> = Vec::new();
+
+    for shape in shapes {
+        match marker_order(&shape) {
+            Some(order) => groups.push((order, Vec::new())),
+            None => match groups.last_mut() {
+                Some((_, group)) => group.push(shape),
+                // can't happen with markers in place, but don't drop anything if it does.
+                None => groups.push((Order::Background, vec![shape])),
+            },
+        }
+    }
+
+    groups.retain(|(_, group)| !group.is_empty());
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::{CentralPanel, RawInput};
+
+    fn texts(shapes: &[ClippedShape]) -> Vec<&str> {
+        shapes
+            .iter()
+            .filter_map(|shape| match &shape.shape {
+                Shape::Text(text) => Some(text.galley.text()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn background_and_foreground_are_split() {
+        let ctx = Context::default();
+
+        let ui = |ctx: &Context| {
+            CentralPanel::default().show(ctx, |ui| ui.label("behind"));
+            Area::new("popup")
+                .order(Order::Foreground)
+                .show(ctx, |ui| ui.label("on top"));
+        };
+
+        // the popup is seen before any marker, like it would be with restored memory.
+        let _ = ctx.run(RawInput::default(), ui);
+
+        // new areas are invisible for a frame, so look at the one after.
+        let mut shapes = Vec::new();
+        for _ in 0..2 {
+            shapes = ctx
+                .run(RawInput::default(), |ctx| {
+                    mark_orders(ctx);
+                    ui(ctx);
+                })
+                .shapes;
+        }
+
+        let groups = split_by_order(shapes);
+        let orders: Vec<Order> = groups.iter().map(|(order, _)| *order).collect();
+        assert_eq!(orders, [Order::Background, Order::Foreground]);
+
+        assert_eq!(texts(&groups[0].1), ["behind"]);
+        assert_eq!(texts(&groups[1].1), ["on top"]);
+
+        assert!(groups
+            .iter()
+            .flat_map(|(_, group)| group)
+            .all(|shape| marker_order(shape).is_none()));
+    }
+}
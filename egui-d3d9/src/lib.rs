@@ -8,10 +8,51 @@ macro_rules! expect {
     };
 }
 
+/// `expect!` for d3d calls, the panic names the HRESULT that was returned.
+macro_rules! dx_expect {
+    ($val:expr, $msg:expr) => {
+        if cfg!(feature = "silent") {
+            $val.unwrap()
+        } else {
+            $val.unwrap_or_else(|e: windows::core::Error| {
+                panic!(
+                    "{}: {:#010X} ({})",
+                    $msg,
+                    e.code().0,
+                    crate::error::describe_hresult(e.code())
+                )
+            })
+        }
+    };
+}
+
+/// prints a warning to stderr, unless we're `silent`.
+macro_rules! warn {
+    ($($arg:tt)*) => {
+        if !cfg!(feature = "silent") {
+            eprintln!("[egui-d3d9] {}", format_args!($($arg)*));
+        }
+    };
+}
+
 mod app;
+mod clipman;
+mod color;
+mod error;
+#[cfg(feature = "ime")]
+mod ime;
 mod inputman;
+mod layers;
 mod mesh;
+mod rawinput;
 mod state;
 mod texman;
+mod theme;
+#[cfg(feature = "touch")]
+mod touch;
 
 pub use app::*;
+pub use clipman::{Clipboard, NullClipboard, WindowsClipboard};
+pub use error::EguiDx9Error;
+pub use inputman::{InputResult, InputSnapshot};
+pub use state::{Orientation, RenderStage};
@@ -1,93 +1,228 @@
-use egui::{Color32, Mesh, Pos2, Rect, TextureId};
+use std::sync::Once;
+
+use egui::{epaint::Vertex, Color32, Mesh, Order, Pos2, Rect, TextureId};
 use windows::Win32::{
-    Foundation::{HANDLE, RECT},
+    Foundation::{E_INVALIDARG, E_POINTER, HANDLE, RECT},
     Graphics::Direct3D9::{
-        IDirect3DDevice9, IDirect3DIndexBuffer9, IDirect3DVertexBuffer9, D3DFMT_INDEX32,
-        D3DFVF_DIFFUSE, D3DFVF_TEX1, D3DFVF_XYZ, D3DLOCK_DISCARD, D3DPOOL_DEFAULT,
-        D3DUSAGE_DYNAMIC, D3DUSAGE_WRITEONLY,
+        IDirect3DDevice9, IDirect3DIndexBuffer9, IDirect3DVertexBuffer9,
+        D3DCREATE_MIXED_VERTEXPROCESSING, D3DCREATE_SOFTWARE_VERTEXPROCESSING,
+        D3DDEVICE_CREATION_PARAMETERS, D3DFMT_INDEX32, D3DFVF_DIFFUSE, D3DFVF_TEX1, D3DFVF_XYZ,
+        D3DLOCK_DISCARD, D3DPOOL_DEFAULT, D3DUSAGE_DYNAMIC, D3DUSAGE_SOFTWAREPROCESSING,
+        D3DUSAGE_WRITEONLY,
     },
 };
 
+use crate::{
+    color::{linear_rgba, ColorOrder, PackedColor},
+    error::EguiDx9Error,
+};
+
 // XYZ is 32 bits completely wasted per vertex.
 // but that's the cost of doing business, I really cba dealing with shaders again
 // although I'll probably do it at some point
 pub const FVF_CUSTOMVERTEX: u32 = D3DFVF_XYZ | D3DFVF_DIFFUSE | D3DFVF_TEX1;
 
-#[repr(C)]
-#[derive(Clone, Copy)]
-pub struct VertexColor {
-    pub b: u8,
-    pub g: u8,
-    pub r: u8,
-    pub a: u8,
-}
-
-impl From<Color32> for VertexColor {
-    fn from(value: Color32) -> Self {
-        let rgba = value.to_tuple();
-        Self {
-            r: rgba.0,
-            g: rgba.1,
-            b: rgba.2,
-            a: rgba.3,
-        }
-    }
-}
-
 pub struct MeshDescriptor {
     pub vertices: usize,
     pub indices: usize,
     pub clip: RECT,
     pub texture_id: TextureId,
+    /// the draw calls it takes, see `draw_ranges`. empty until the device's limits are applied.
+    pub ranges: Vec<DrawRange>,
+    /// the egui `Order` it was painted in, for `present_layer`.
+    pub order: Order,
+    /// which `EguiDx9::add_context` context it's from, `None` for the main one.
+    pub context: Option<usize>,
+}
+
+/// one `DrawIndexedPrimitive` worth of a mesh.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DrawRange {
+    /// first index, relative to the start of the mesh.
+    pub first: usize,
+    pub triangles: usize,
+    /// the lowest vertex the range uses, and how many from there up to its highest.
+    pub min_vertex: u32,
+    pub num_vertices: u32,
 }
 
 impl MeshDescriptor {
-    pub fn from_mesh(mesh: Mesh, scissors: Rect) -> Option<(Self, Vec<GpuVertex>, Vec<u32>)> {
-        if mesh.indices.is_empty() || mesh.indices.len() % 3 != 0 {
+    /// describes where `mesh` will live in our buffers, or `None` if it wouldn't draw anything.
+    /// non-finite positions and uvs are zeroed first, the gpu does anything from garbage to hanging on those.
+    pub fn from_mesh(mesh: &mut Mesh, scissors: Rect) -> Option<Self> {
+        sanitize(mesh);
+
+        let clip = RECT {
+            left: scissors.left() as _,
+            top: scissors.top() as _,
+            right: scissors.right() as _,
+            bottom: scissors.bottom() as _,
+        };
+
+        // a clip rect that collapses to nothing would just waste a scissor and a draw call.
+        let clipped_away = clip.right <= clip.left || clip.bottom <= clip.top;
+
+        if clipped_away || mesh.indices.is_empty() || mesh.indices.len() % 3 != 0 {
             None
         } else {
-            let vertices: Vec<GpuVertex> = mesh
-                .vertices
-                .into_iter()
-                .map(|v| GpuVertex {
-                    pos: [v.pos.x, v.pos.y, 0f32],
-                    uv: v.uv,
-                    color: v.color.into(),
-                })
-                .collect();
-
-            Some((
-                Self {
-                    vertices: vertices.len(),
-                    indices: mesh.indices.len(),
-                    clip: RECT {
-                        left: scissors.left() as _,
-                        top: scissors.top() as _,
-                        right: scissors.right() as _,
-                        bottom: scissors.bottom() as _,
-                    },
-                    texture_id: mesh.texture_id,
-                },
-                vertices,
-                mesh.indices,
-            ))
+            Some(Self {
+                vertices: mesh.vertices.len(),
+                indices: mesh.indices.len(),
+                clip,
+                texture_id: mesh.texture_id,
+                ranges: Vec::new(),
+                order: Order::Background,
+                context: None,
+            })
+        }
+    }
+}
+
+/// cuts `mesh` down to `clip` on the cpu, for drawing without scissor rects.
+/// triangles entirely inside are kept as they are, the ones crossing an edge are split along it.
+pub fn clip_mesh(mesh: Mesh, clip: Rect) -> Mesh {
+    if mesh.vertices.iter().all(|v| clip.contains(v.pos)) {
+        return mesh;
+    }
+
+    // the original vertices stay, even if no triangle uses them anymore.
+    let mut clipped = Mesh {
+        indices: Vec::with_capacity(mesh.indices.len()),
+        vertices: mesh.vertices.clone(),
+        texture_id: mesh.texture_id,
+    };
+
+    mesh.indices.chunks_exact(3).for_each(|tri| {
+        let corners = [tri[0], tri[1], tri[2]].map(|idx| mesh.vertices[idx as usize]);
+
+        if corners.iter().all(|v| clip.contains(v.pos)) {
+            clipped.indices.extend_from_slice(tri);
+            return;
+        }
+
+        let polygon = clip_polygon(corners.to_vec(), clip);
+
+        if polygon.len() < 3 {
+            return;
+        }
+
+        // the clipped polygon is convex, so a fan covers it.
+        let base = clipped.vertices.len() as u32;
+        (1..polygon.len() as u32 - 1)
+            .for_each(|i| clipped.indices.extend([base, base + i, base + i + 1]));
+        clipped.vertices.extend(polygon);
+    });
+
+    clipped
+}
+
+/// sutherland-hodgman, one edge of `clip` at a time.
+fn clip_polygon(mut polygon: Vec<Vertex>, clip: Rect) -> Vec<Vertex> {
+    // how far inside each edge a vertex is, negative when outside.
+    let edges: [fn(&Vertex, &Rect) -> f32; 4] = [
+        |v, clip| v.pos.x - clip.min.x,
+        |v, clip| clip.max.x - v.pos.x,
+        |v, clip| v.pos.y - clip.min.y,
+        |v, clip| clip.max.y - v.pos.y,
+    ];
+
+    for inside in edges {
+        let input = std::mem::take(&mut polygon);
+
+        input.iter().enumerate().for_each(|(i, a)| {
+            let b = &input[(i + 1) % input.len()];
+            let (da, db) = (inside(a, &clip), inside(b, &clip));
+
+            if da >= 0. {
+                polygon.push(*a);
+            }
+
+            if (da >= 0.) != (db >= 0.) {
+                polygon.push(lerp_vertex(a, b, da / (da - db)));
+            }
+        });
+
+        if polygon.is_empty() {
+            break;
         }
     }
+
+    polygon
+}
+
+fn lerp_vertex(a: &Vertex, b: &Vertex, t: f32) -> Vertex {
+    let [a_color, b_color] = [a.color, b.color].map(|c| c.to_array().map(|c| c as f32));
+    let [r, g, bl, al] =
+        [0, 1, 2, 3].map(|i| (a_color[i] + (b_color[i] - a_color[i]) * t).round() as u8);
+
+    Vertex {
+        pos: a.pos + (b.pos - a.pos) * t,
+        uv: a.uv + (b.uv - a.uv) * t,
+        color: Color32::from_rgba_premultiplied(r, g, bl, al),
+    }
+}
+
+/// whether every vertex of `mesh` is fully transparent, so drawing it would change nothing.
+pub fn is_transparent(mesh: &Mesh) -> bool {
+    mesh.vertices.iter().all(|v| v.color.a() == 0)
+}
+
+/// replaces NaN and infinite positions and uvs with 0, which some degenerate widgets produce.
+fn sanitize(mesh: &mut Mesh) {
+    let finite = |p: Pos2| p.x.is_finite() && p.y.is_finite();
+
+    if mesh.vertices.iter().all(|v| finite(v.pos) && finite(v.uv)) {
+        return;
+    }
+
+    static WARNED: Once = Once::new();
+    WARNED.call_once(|| warn!("zeroing non-finite vertex positions or uvs"));
+
+    let zeroed = |p: Pos2| Pos2::new(finite_or_zero(p.x), finite_or_zero(p.y));
+
+    mesh.vertices.iter_mut().for_each(|v| {
+        v.pos = zeroed(v.pos);
+        v.uv = zeroed(v.uv);
+    });
+}
+
+fn finite_or_zero(v: f32) -> f32 {
+    if v.is_finite() {
+        v
+    } else {
+        0.
+    }
 }
 
 #[repr(C)]
 #[derive(Clone, Copy)]
 pub struct GpuVertex {
     pos: [f32; 3],
-    color: VertexColor,
+    color: PackedColor,
     uv: Pos2,
 }
 
+impl GpuVertex {
+    pub fn new(v: &Vertex, linear_colors: bool, order: ColorOrder) -> Self {
+        Self {
+            pos: [v.pos.x, v.pos.y, 0f32],
+            uv: v.uv,
+            color: order.pack(if linear_colors {
+                linear_rgba(v.color)
+            } else {
+                v.color.to_array()
+            }),
+        }
+    }
+}
+
+#[derive(Default)]
 pub struct Buffers {
     pub vtx: Option<IDirect3DVertexBuffer9>,
     pub idx: Option<IDirect3DIndexBuffer9>,
     vtx_size: usize,
     idx_size: usize,
+    usage: u32,
 }
 
 impl Buffers {
@@ -96,11 +231,21 @@ impl Buffers {
         vtx_count: usize,
         idx_count: usize,
     ) -> Buffers {
+        // creation parameters never change, so ask once here instead of on every (re)creation.
+        let usage = buffer_usage(device);
+
         Buffers {
             vtx_size: vtx_count,
             idx_size: idx_count,
-            vtx: Some(Self::create_vertex_buffer(device, vtx_count)),
-            idx: Some(Self::create_index_buffer(device, idx_count)),
+            vtx: Some(expect!(
+                Self::create_vertex_buffer(device, vtx_count, usage),
+                "unable to create vertex buffer"
+            )),
+            idx: Some(expect!(
+                Self::create_index_buffer(device, idx_count, usage),
+                "unable to create index buffer"
+            )),
+            usage,
         }
     }
 
@@ -109,105 +254,450 @@ impl Buffers {
         self.idx = None;
     }
 
-    fn create_vertex_buffer(device: &IDirect3DDevice9, vertices: usize) -> IDirect3DVertexBuffer9 {
+    fn create_vertex_buffer(
+        device: &IDirect3DDevice9,
+        vertices: usize,
+        usage: u32,
+    ) -> Result<IDirect3DVertexBuffer9, EguiDx9Error> {
         unsafe {
             let mut vertex_buffer: Option<IDirect3DVertexBuffer9> = None;
-            expect!(
-                device.CreateVertexBuffer(
-                    (vertices * std::mem::size_of::<GpuVertex>()) as u32,
-                    (D3DUSAGE_DYNAMIC | D3DUSAGE_WRITEONLY) as _,
+            device
+                .CreateVertexBuffer(
+                    byte_size::<GpuVertex>(vertices)?,
+                    usage,
                     FVF_CUSTOMVERTEX,
                     D3DPOOL_DEFAULT,
                     &mut vertex_buffer,
-                    std::ptr::null_mut::<HANDLE>()
-                ),
-                "Failed to create vertex buffer"
-            );
+                    std::ptr::null_mut::<HANDLE>(),
+                )
+                .map_err(|e| EguiDx9Error::new("unable to create vertex buffer", e))?;
 
-            expect!(vertex_buffer, "unable to create vertex buffer")
+            vertex_buffer.ok_or_else(|| {
+                EguiDx9Error::new("unable to create vertex buffer", E_POINTER.into())
+            })
         }
     }
 
-    fn create_index_buffer(device: &IDirect3DDevice9, indices: usize) -> IDirect3DIndexBuffer9 {
+    fn create_index_buffer(
+        device: &IDirect3DDevice9,
+        indices: usize,
+        usage: u32,
+    ) -> Result<IDirect3DIndexBuffer9, EguiDx9Error> {
         unsafe {
             let mut index_buffer: Option<IDirect3DIndexBuffer9> = None;
-            expect!(
-                device.CreateIndexBuffer(
-                    (indices * std::mem::size_of::<u32>()) as u32,
-                    (D3DUSAGE_DYNAMIC | D3DUSAGE_WRITEONLY) as _,
+            device
+                .CreateIndexBuffer(
+                    byte_size::<u32>(indices)?,
+                    usage,
                     D3DFMT_INDEX32,
                     D3DPOOL_DEFAULT,
                     &mut index_buffer,
-                    std::ptr::null_mut::<HANDLE>()
-                ),
-                "Failed to create index buffer"
-            );
+                    std::ptr::null_mut::<HANDLE>(),
+                )
+                .map_err(|e| EguiDx9Error::new("unable to create index buffer", e))?;
 
-            expect!(index_buffer, "unable to create index buffer")
+            index_buffer
+                .ok_or_else(|| EguiDx9Error::new("unable to create index buffer", E_POINTER.into()))
         }
     }
 
-    pub fn update_vertex_buffer(&mut self, device: &IDirect3DDevice9, vertices: &[GpuVertex]) {
-        unsafe {
-            let buf_len = vertices.len();
+    /// makes sure the buffers can hold this many vertices and indices, growing them if not.
+    /// they at least double each time, so a steadily growing UI doesn't reallocate every frame.
+    /// fails, leaving both buffers as they were, if either can't be created.
+    pub fn reserve(
+        &mut self,
+        device: &IDirect3DDevice9,
+        vertices: usize,
+        indices: usize,
+    ) -> Result<(), EguiDx9Error> {
+        let vtx_size = grown_size(self.vtx_size, vertices);
+        let idx_size = grown_size(self.idx_size, indices);
 
-            if self.vtx_size < buf_len {
-                let new_size = buf_len + 1024;
-                self.vtx = Some(Self::create_vertex_buffer(device, new_size));
-                self.vtx_size = new_size;
-            }
+        // create both before touching either, so a failure can't leave them mismatched.
+        let vtx = (vtx_size != self.vtx_size)
+            .then(|| Self::create_vertex_buffer(device, vtx_size, self.usage))
+            .transpose()?;
+        let idx = (idx_size != self.idx_size)
+            .then(|| Self::create_index_buffer(device, idx_size, self.usage))
+            .transpose()?;
+
+        if let Some(vtx) = vtx {
+            self.vtx = Some(vtx);
+            self.vtx_size = vtx_size;
+        }
+
+        if let Some(idx) = idx {
+            self.idx = Some(idx);
+            self.idx_size = idx_size;
+        }
+
+        Ok(())
+    }
+
+    /// locks the first `count` vertices and lets `write` fill them in place.
+    /// writing straight into the locked buffer saves building and copying a staging `Vec`.
+    pub fn write_vertices(&mut self, count: usize, write: impl FnOnce(&mut [GpuVertex])) {
+        // locking 0 bytes locks the entire buffer.
+        if count == 0 {
+            return;
+        }
 
+        // the slice below must never reach past what was actually allocated.
+        // this also means its byte size already fit when the buffer was created.
+        if count > self.vtx_size {
+            panic!("vertex write exceeds vertex buffer size");
+        }
+
+        unsafe {
             let vtx = expect!(self.vtx.as_mut(), "unable to get vertex buffer");
 
-            let mut buffer: *mut GpuVertex = std::mem::zeroed();
+            let mut buffer: *mut GpuVertex = std::ptr::null_mut();
 
-            expect!(
+            dx_expect!(
                 vtx.Lock(
                     0,
-                    vertices.len() as u32 * std::mem::size_of::<GpuVertex>() as u32,
-                    std::mem::transmute(&mut buffer),
+                    expect!(byte_size::<GpuVertex>(count), "vertex write size overflows"),
+                    &mut buffer as *mut *mut GpuVertex as *mut *mut std::ffi::c_void,
                     D3DLOCK_DISCARD as _
                 ),
                 "unable to lock vertex buffer"
             );
 
-            let buffer = std::slice::from_raw_parts_mut(buffer, vertices.len() as _);
+            write(std::slice::from_raw_parts_mut(buffer, count));
 
-            buffer.copy_from_slice(vertices);
-
-            expect!(vtx.Unlock(), "unable to unlock vtx buffer");
+            dx_expect!(vtx.Unlock(), "unable to unlock vtx buffer");
         }
     }
 
-    pub fn update_index_buffer(&mut self, device: &IDirect3DDevice9, indices: &[u32]) {
-        unsafe {
-            let buf_len = indices.len();
+    /// locks the first `count` indices and lets `write` fill them in place.
+    pub fn write_indices(&mut self, count: usize, write: impl FnOnce(&mut [u32])) {
+        if count == 0 {
+            return;
+        }
 
-            if self.idx_size < buf_len {
-                let new_size = buf_len + 1024;
-                self.idx = Some(Self::create_index_buffer(device, new_size));
-                self.idx_size = new_size;
-            }
+        if count > self.idx_size {
+            panic!("index write exceeds index buffer size");
+        }
 
+        unsafe {
             let idx = expect!(self.idx.as_mut(), "unable to get index buffer");
 
-            let mut buffer: *mut u32 = std::mem::zeroed();
+            let mut buffer: *mut u32 = std::ptr::null_mut();
 
-            expect!(
+            dx_expect!(
                 idx.Lock(
                     0,
-                    indices.len() as u32 * std::mem::size_of::<u32>() as u32,
+                    expect!(byte_size::<u32>(count), "index write size overflows"),
                     &mut buffer as *mut *mut u32 as *mut *mut std::ffi::c_void,
                     D3DLOCK_DISCARD as _
                 ),
                 "unable to lock index buffer"
             );
 
-            let buffer = std::slice::from_raw_parts_mut(buffer, indices.len() as _);
+            write(std::slice::from_raw_parts_mut(buffer, count));
+
+            dx_expect!(idx.Unlock(), "unable to unlock idx buffer");
+        }
+    }
+}
+
+/// buffers a device does vertex processing for in software have to say so, or nothing is drawn.
+/// mixed devices can switch at any time, and such buffers work in either mode.
+fn buffer_usage(device: &IDirect3DDevice9) -> u32 {
+    let usage = (D3DUSAGE_DYNAMIC | D3DUSAGE_WRITEONLY) as u32;
+
+    let mut params = D3DDEVICE_CREATION_PARAMETERS::default();
+    if unsafe { device.GetCreationParameters(&mut params) }.is_err() {
+        return usage;
+    }
+
+    let software = (D3DCREATE_SOFTWARE_VERTEXPROCESSING | D3DCREATE_MIXED_VERTEXPROCESSING) as u32;
+
+    if params.BehaviorFlags & software != 0 {
+        usage | D3DUSAGE_SOFTWAREPROCESSING as u32
+    } else {
+        usage
+    }
+}
+
+/// size in bytes of `count` elements, as d3d9 wants it.
+/// a UI large enough to overflow this is bogus, so refuse instead of silently wrapping.
+fn byte_size<T>(count: usize) -> Result<u32, EguiDx9Error> {
+    count
+        .checked_mul(std::mem::size_of::<T>())
+        .and_then(|size| u32::try_from(size).ok())
+        .ok_or_else(|| EguiDx9Error::new("buffer size exceeds u32::MAX bytes", E_INVALIDARG.into()))
+}
+
+/// splits a triangle list into draw calls the device can take: at most `max_prims` triangles,
+/// using at most `max_vertices` vertices from the lowest index to the highest.
+/// a single triangle that spans more than that is still drawn, on its own.
+pub fn draw_ranges(indices: &[u32], max_prims: usize, max_vertices: u32) -> Vec<DrawRange> {
+    let mut ranges = Vec::new();
+    let mut current: Option<DrawRange> = None;
+
+    indices
+        .chunks_exact(3)
+        .enumerate()
+        .for_each(|(triangle, tri)| {
+            let lo = tri[0].min(tri[1]).min(tri[2]);
+            let hi = tri[0].max(tri[1]).max(tri[2]);
+
+            if let Some(range) = current.as_mut() {
+                let min = range.min_vertex.min(lo);
+                let max = (range.min_vertex + range.num_vertices - 1).max(hi);
+
+                if range.triangles < max_prims && max - min < max_vertices {
+                    range.triangles += 1;
+                    range.min_vertex = min;
+                    range.num_vertices = max - min + 1;
+                    return;
+                }
+
+                ranges.push(*range);
+            }
+
+            current = Some(DrawRange {
+                first: triangle * 3,
+                triangles: 1,
+                min_vertex: lo,
+                num_vertices: (hi - lo).saturating_add(1),
+            });
+        });
+
+    ranges.extend(current);
+    ranges
+}
+
+/// how many elements a buffer holding `current` is regrown to, so it fits `wanted`.
+fn grown_size(current: usize, wanted: usize) -> usize {
+    if current >= wanted {
+        current
+    } else {
+        wanted.max(current.saturating_mul(2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::pos2;
+
+    fn triangle(points: [Pos2; 3], color: Color32) -> Mesh {
+        let mut mesh = Mesh::default();
+        points
+            .iter()
+            .for_each(|&pos| mesh.colored_vertex(pos, color));
+        mesh.add_triangle(0, 1, 2);
+        mesh
+    }
+
+    #[test]
+    fn empty_clip_rects_are_skipped() {
+        let tri = || triangle([pos2(0., 0.), pos2(10., 0.), pos2(0., 10.)], Color32::WHITE);
+
+        // both collapse to nothing once cast to whole pixels.
+        let zero_height = Rect::from_min_max(pos2(0., 5.), pos2(100., 5.4));
+        let zero_width = Rect::from_min_max(pos2(5., 0.), pos2(5., 100.));
+
+        assert!(MeshDescriptor::from_mesh(&mut tri(), zero_height).is_none());
+        assert!(MeshDescriptor::from_mesh(&mut tri(), zero_width).is_none());
 
-            buffer.copy_from_slice(indices);
+        let clip = Rect::from_min_max(pos2(0., 0.), pos2(100., 50.));
+        let desc = MeshDescriptor::from_mesh(&mut tri(), clip).expect("mesh was skipped");
 
-            expect!(idx.Unlock(), "unable to unlock idx buffer");
+        assert_eq!((desc.vertices, desc.indices), (3, 3));
+        let RECT {
+            left,
+            top,
+            right,
+            bottom,
+        } = desc.clip;
+        assert_eq!((left, top, right, bottom), (0, 0, 100, 50));
+    }
+
+    #[test]
+    fn byte_size_refuses_overflow() {
+        assert_eq!(byte_size::<u32>(16), Ok(64));
+        assert_eq!(
+            byte_size::<u32>(u32::MAX as usize / 4),
+            Ok(u32::MAX / 4 * 4)
+        );
+
+        assert!(byte_size::<u32>(u32::MAX as usize / 4 + 1).is_err());
+        assert!(byte_size::<GpuVertex>(usize::MAX / std::mem::size_of::<GpuVertex>()).is_err());
+        assert!(byte_size::<GpuVertex>(usize::MAX).is_err());
+    }
+
+    #[test]
+    fn buffers_grow_geometrically() {
+        assert_eq!(grown_size(1024, 512), 1024);
+        assert_eq!(grown_size(1024, 1024), 1024);
+        assert_eq!(grown_size(1024, 1025), 2048);
+        assert_eq!(grown_size(1024, 5000), 5000);
+        assert_eq!(grown_size(0, 10), 10);
+        assert_eq!(grown_size(usize::MAX / 2 + 1, usize::MAX), usize::MAX);
+
+        // a UI that grows by one vertex a frame only reallocates a handful of times.
+        let mut size = 1024;
+        let mut reallocations = 0;
+
+        (1..=100_000).for_each(|wanted| {
+            let grown = grown_size(size, wanted);
+
+            assert!(grown >= size && grown >= wanted);
+
+            if grown != size {
+                reallocations += 1;
+                size = grown;
+            }
+        });
+
+        assert_eq!(reallocations, 7);
+    }
+
+    #[test]
+    fn large_meshes_are_split_to_fit_device_limits() {
+        let strip = |triangles: u32| -> Vec<u32> {
+            (0..triangles).flat_map(|i| [i, i + 1, i + 2]).collect()
+        };
+        let range = |first, triangles, min_vertex, num_vertices| DrawRange {
+            first,
+            triangles,
+            min_vertex,
+            num_vertices,
+        };
+
+        assert_eq!(draw_ranges(&[], 100, 65535), []);
+        assert_eq!(draw_ranges(&strip(1), 100, 65535), [range(0, 1, 0, 3)]);
+        assert_eq!(
+            draw_ranges(&strip(100), 100, 65535),
+            [range(0, 100, 0, 102)]
+        );
+        assert_eq!(
+            draw_ranges(&strip(101), 100, 65535),
+            [range(0, 100, 0, 102), range(300, 1, 100, 3)]
+        );
+
+        // the vertex limit splits too, and each range only claims the vertices it uses.
+        assert_eq!(
+            draw_ranges(&strip(10), 100, 6),
+            [range(0, 4, 0, 6), range(12, 4, 4, 6), range(24, 2, 8, 4)]
+        );
+
+        // a lone triangle wider than the limit still gets drawn.
+        assert_eq!(
+            draw_ranges(&[0, 1, 70000], 100, 65535),
+            [range(0, 1, 0, 70001)]
+        );
+
+        // every triangle is drawn exactly once, and every range fits.
+        for triangles in [99, 100, 101, 199, 200, 201, 65535 * 3 + 7] {
+            for (max_prims, max_vertices) in [(65535, 0xFFFFFF), (100, 65535), (65535, 250)] {
+                let ranges = draw_ranges(&strip(triangles as u32), max_prims, max_vertices);
+
+                assert!(ranges.iter().all(|range| range.triangles > 0
+                    && range.triangles <= max_prims
+                    && range.num_vertices <= max_vertices));
+                assert!(ranges
+                    .windows(2)
+                    .all(|pair| pair[0].first + pair[0].triangles * 3 == pair[1].first));
+                assert_eq!(
+                    ranges.iter().map(|range| range.triangles).sum::<usize>(),
+                    triangles
+                );
+            }
         }
     }
+
+    fn area(mesh: &Mesh) -> f32 {
+        mesh.indices
+            .chunks_exact(3)
+            .map(|tri| {
+                let [a, b, c] = [tri[0], tri[1], tri[2]].map(|idx| mesh.vertices[idx as usize].pos);
+                ((b - a).x * (c - a).y - (b - a).y * (c - a).x).abs() / 2.
+            })
+            .sum()
+    }
+
+    fn assert_within(mesh: &Mesh, clip: Rect) {
+        mesh.indices.iter().for_each(|&idx| {
+            assert!(
+                (idx as usize) < mesh.vertices.len(),
+                "index {idx} out of range"
+            );
+            let pos = mesh.vertices[idx as usize].pos;
+            assert!(
+                clip.expand(1e-3).contains(pos),
+                "{pos:?} is outside {clip:?}"
+            );
+        });
+    }
+
+    #[test]
+    fn cpu_clipping() {
+        let clip = Rect::from_min_max(pos2(0., 0.), pos2(10., 30.));
+
+        let inside = triangle([pos2(1., 1.), pos2(9., 1.), pos2(1., 9.)], Color32::WHITE);
+        let clipped = clip_mesh(inside.clone(), clip);
+        assert_eq!(clipped.indices, inside.indices);
+        assert_eq!(clipped.vertices, inside.vertices);
+
+        let outside = triangle(
+            [pos2(50., 50.), pos2(60., 50.), pos2(50., 60.)],
+            Color32::WHITE,
+        );
+        let clipped = clip_mesh(outside, clip);
+        assert!(clipped.indices.is_empty());
+
+        // crosses the right edge, what's left is the part of x + y <= 20 with x <= 10.
+        let straddling = triangle([pos2(0., 0.), pos2(20., 0.), pos2(0., 20.)], Color32::WHITE);
+        let clipped = clip_mesh(straddling, clip);
+        assert!(!clipped.indices.is_empty());
+        assert_eq!(clipped.indices.len() % 3, 0);
+        assert_within(&clipped, clip);
+        assert!((area(&clipped) - 150.).abs() < 1e-3);
+
+        // a triangle covering the whole clip rect is cut down to exactly it.
+        let covering = triangle(
+            [pos2(-50., -50.), pos2(100., -50.), pos2(-50., 100.)],
+            Color32::WHITE,
+        );
+        let clipped = clip_mesh(covering, clip);
+        assert_within(&clipped, clip);
+        assert!((area(&clipped) - clip.area()).abs() < 1e-3);
+    }
+
+    #[test]
+    fn non_finite_positions_and_uvs_are_zeroed() {
+        let mut mesh = triangle(
+            [pos2(f32::NAN, 1.), pos2(10., f32::INFINITY), pos2(0., 10.)],
+            Color32::WHITE,
+        );
+        mesh.vertices[1].uv = pos2(0.5, 0.25);
+        mesh.vertices[2].uv = pos2(f32::NEG_INFINITY, f32::NAN);
+
+        let clip = Rect::from_min_max(pos2(0., 0.), pos2(100., 100.));
+        assert!(MeshDescriptor::from_mesh(&mut mesh, clip).is_some());
+
+        assert_eq!(mesh.vertices[0].pos, pos2(0., 1.));
+        assert_eq!(mesh.vertices[1].pos, pos2(10., 0.));
+        assert_eq!(mesh.vertices[2].pos, pos2(0., 10.));
+        // finite values are left alone.
+        assert_eq!(mesh.vertices[1].uv, pos2(0.5, 0.25));
+        assert_eq!(mesh.vertices[2].uv, pos2(0., 0.));
+    }
+
+    #[test]
+    fn transparent_meshes_are_detected() {
+        let points = [pos2(0., 0.), pos2(10., 0.), pos2(0., 10.)];
+
+        assert!(is_transparent(&triangle(points, Color32::TRANSPARENT)));
+
+        let mut partly = triangle(points, Color32::TRANSPARENT);
+        partly.vertices[1].color = Color32::from_rgba_premultiplied(0, 0, 0, 1);
+        assert!(!is_transparent(&partly));
+
+        assert!(!is_transparent(&triangle(points, Color32::WHITE)));
+    }
 }
@@ -0,0 +1,86 @@
+use egui::Vec2;
+use windows::Win32::{
+    Foundation::HWND,
+    UI::Input::{
+        GetRawInputData, GetRegisteredRawInputDevices, RegisterRawInputDevices, HRAWINPUT,
+        MOUSE_MOVE_ABSOLUTE, RAWINPUT, RAWINPUTDEVICE, RAWINPUTDEVICE_FLAGS, RAWINPUTHEADER,
+        RID_INPUT, RIM_TYPEMOUSE,
+    },
+};
+
+// HID_USAGE_PAGE_GENERIC and HID_USAGE_GENERIC_MOUSE.
+const USAGE_PAGE_GENERIC: u16 = 0x01;
+const USAGE_MOUSE: u16 = 0x02;
+
+/// makes sure `WM_INPUT` is sent for the mouse.
+/// registrations are per process, so if the game already has one we keep it instead of stealing it.
+pub fn register_mouse(hwnd: HWND) -> windows::core::Result<()> {
+    if is_mouse_registered() {
+        return Ok(());
+    }
+
+    unsafe {
+        RegisterRawInputDevices(
+            &[RAWINPUTDEVICE {
+                usUsagePage: USAGE_PAGE_GENERIC,
+                usUsage: USAGE_MOUSE,
+                dwFlags: RAWINPUTDEVICE_FLAGS(0),
+                hwndTarget: hwnd,
+            }],
+            std::mem::size_of::<RAWINPUTDEVICE>() as _,
+        )
+    }
+}
+
+/// how far the mouse moved according to a `WM_INPUT` message, `None` if it wasn't relative mouse movement.
+/// tablets and remote desktop report absolute positions instead, those are ignored.
+pub fn mouse_delta(lparam: isize) -> Option<Vec2> {
+    let mut input = RAWINPUT::default();
+    let mut size = std::mem::size_of::<RAWINPUT>() as u32;
+
+    let read = unsafe {
+        GetRawInputData(
+            HRAWINPUT(lparam),
+            RID_INPUT,
+            Some(&mut input as *mut RAWINPUT as _),
+            &mut size,
+            std::mem::size_of::<RAWINPUTHEADER>() as _,
+        )
+    };
+
+    if read == u32::MAX || input.header.dwType != RIM_TYPEMOUSE.0 {
+        return None;
+    }
+
+    let mouse = unsafe { input.data.mouse };
+
+    if mouse.usFlags.0 & MOUSE_MOVE_ABSOLUTE.0 != 0 {
+        return None;
+    }
+
+    Some(Vec2::new(mouse.lLastX as _, mouse.lLastY as _))
+}
+
+fn is_mouse_registered() -> bool {
+    let mut count = 0;
+    let size = std::mem::size_of::<RAWINPUTDEVICE>() as u32;
+
+    unsafe {
+        // the first call only asks how many there are.
+        GetRegisteredRawInputDevices(None, &mut count, size);
+
+        if count == 0 {
+            return false;
+        }
+
+        let mut devices = vec![RAWINPUTDEVICE::default(); count as usize];
+
+        if GetRegisteredRawInputDevices(Some(devices.as_mut_ptr()), &mut count, size) == u32::MAX {
+            return false;
+        }
+
+        devices
+            .iter()
+            .any(|dev| dev.usUsagePage == USAGE_PAGE_GENERIC && dev.usUsage == USAGE_MOUSE)
+    }
+}
@@ -1,47 +1,342 @@
 use windows::{
     Foundation::Numerics::Matrix4x4,
-    Win32::Graphics::Direct3D9::{
-        IDirect3DDevice9, IDirect3DStateBlock9, IDirect3DSurface9, D3DBACKBUFFER_TYPE_MONO,
-        D3DBLENDOP_ADD, D3DBLEND_INVSRCALPHA, D3DBLEND_ONE, D3DCULL_NONE, D3DFILL_SOLID,
-        D3DMULTISAMPLE_TYPE, D3DRS_ALPHABLENDENABLE, D3DRS_ALPHATESTENABLE, D3DRS_BLENDOP,
-        D3DRS_BLENDOPALPHA, D3DRS_CLIPPING, D3DRS_COLORWRITEENABLE, D3DRS_CULLMODE,
-        D3DRS_DESTBLEND, D3DRS_DESTBLENDALPHA, D3DRS_FILLMODE, D3DRS_FOGENABLE, D3DRS_LASTPIXEL,
-        D3DRS_LIGHTING, D3DRS_RANGEFOGENABLE, D3DRS_SCISSORTESTENABLE,
-        D3DRS_SEPARATEALPHABLENDENABLE, D3DRS_SHADEMODE, D3DRS_SPECULARENABLE, D3DRS_SRCBLEND,
-        D3DRS_SRCBLENDALPHA, D3DRS_SRGBWRITEENABLE, D3DRS_STENCILENABLE, D3DRS_TEXTUREFACTOR,
-        D3DRS_ZENABLE, D3DRS_ZWRITEENABLE, D3DSAMP_ADDRESSU, D3DSAMP_ADDRESSV, D3DSAMP_ADDRESSW,
-        D3DSAMP_BORDERCOLOR, D3DSAMP_MAGFILTER, D3DSAMP_MINFILTER, D3DSAMP_MIPFILTER, D3DSBT_ALL,
-        D3DSHADE_GOURAUD, D3DSURFACE_DESC, D3DTADDRESS_CLAMP, D3DTA_CURRENT, D3DTA_DIFFUSE,
-        D3DTA_TEXTURE, D3DTEXF_LINEAR, D3DTEXF_NONE, D3DTOP_DISABLE, D3DTOP_MODULATE,
-        D3DTRANSFORMSTATETYPE, D3DTSS_ALPHAARG0, D3DTSS_ALPHAARG1, D3DTSS_ALPHAARG2,
-        D3DTSS_ALPHAOP, D3DTSS_COLORARG0, D3DTSS_COLORARG1, D3DTSS_COLORARG2, D3DTSS_COLOROP,
-        D3DTS_PROJECTION, D3DTS_VIEW, D3DVIEWPORT9,
+    Win32::{
+        Foundation::E_FAIL,
+        Graphics::Direct3D9::{
+            IDirect3DDevice9, IDirect3DStateBlock9, IDirect3DSurface9, IDirect3DVertexDeclaration9,
+            D3DBACKBUFFER_TYPE, D3DBLENDOP_ADD, D3DBLEND_INVSRCALPHA, D3DBLEND_ONE, D3DCULL_NONE,
+            D3DFILL_SOLID, D3DFMT_A8R8G8B8, D3DFMT_X8R8G8B8, D3DLOCKED_RECT, D3DLOCK_READONLY,
+            D3DMULTISAMPLE_NONE, D3DMULTISAMPLE_TYPE, D3DPOOL_SYSTEMMEM, D3DRS_ALPHABLENDENABLE,
+            D3DRS_ALPHATESTENABLE, D3DRS_BLENDOP, D3DRS_BLENDOPALPHA, D3DRS_CLIPPING,
+            D3DRS_COLORWRITEENABLE, D3DRS_CULLMODE, D3DRS_DESTBLEND, D3DRS_DESTBLENDALPHA,
+            D3DRS_FILLMODE, D3DRS_FOGENABLE, D3DRS_LASTPIXEL, D3DRS_LIGHTING,
+            D3DRS_MULTISAMPLEANTIALIAS, D3DRS_RANGEFOGENABLE, D3DRS_SCISSORTESTENABLE,
+            D3DRS_SEPARATEALPHABLENDENABLE, D3DRS_SHADEMODE, D3DRS_SPECULARENABLE, D3DRS_SRCBLEND,
+            D3DRS_SRCBLENDALPHA, D3DRS_SRGBWRITEENABLE, D3DRS_STENCILENABLE, D3DRS_TEXTUREFACTOR,
+            D3DRS_ZENABLE, D3DRS_ZWRITEENABLE, D3DSAMP_ADDRESSU, D3DSAMP_ADDRESSV,
+            D3DSAMP_ADDRESSW, D3DSAMP_BORDERCOLOR, D3DSAMP_MAGFILTER, D3DSAMP_MINFILTER,
+            D3DSAMP_MIPFILTER, D3DSAMP_SRGBTEXTURE, D3DSBT_ALL, D3DSHADE_GOURAUD, D3DSURFACE_DESC,
+            D3DTADDRESS_CLAMP, D3DTA_CURRENT, D3DTA_DIFFUSE, D3DTA_TEXTURE, D3DTEXF_LINEAR,
+            D3DTEXF_NONE, D3DTOP_DISABLE, D3DTOP_MODULATE, D3DTRANSFORMSTATETYPE, D3DTSS_ALPHAARG0,
+            D3DTSS_ALPHAARG1, D3DTSS_ALPHAARG2, D3DTSS_ALPHAOP, D3DTSS_COLORARG0, D3DTSS_COLORARG1,
+            D3DTSS_COLORARG2, D3DTSS_COLOROP, D3DTS_PROJECTION, D3DTS_VIEW, D3DVIEWPORT9,
+        },
     },
 };
 
-use crate::mesh::FVF_CUSTOMVERTEX;
+use egui::{Color32, ColorImage, Pos2, Rect};
+
+use crate::{color::ColorOrder, mesh::FVF_CUSTOMVERTEX};
+
+/// clockwise rotation of the overlay relative to the back buffer.
+/// used for rotated/portrait displays where the host renders rotated.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Orientation {
+    #[default]
+    Deg0,
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+impl Orientation {
+    /// size of the space egui lays out in, given the physical back buffer size.
+    pub fn logical_size(self, w: f32, h: f32) -> (f32, f32) {
+        match self {
+            Orientation::Deg0 | Orientation::Deg180 => (w, h),
+            Orientation::Deg90 | Orientation::Deg270 => (h, w),
+        }
+    }
+
+    /// maps a physical client position into egui's (rotated) space.
+    /// this is the inverse of what `rotate_projection` does to our geometry.
+    pub fn to_logical(self, pos: Pos2, w: f32, h: f32) -> Pos2 {
+        match self {
+            Orientation::Deg0 => pos,
+            Orientation::Deg90 => Pos2::new(pos.y, w - pos.x),
+            Orientation::Deg180 => Pos2::new(w - pos.x, h - pos.y),
+            Orientation::Deg270 => Pos2::new(h - pos.y, pos.x),
+        }
+    }
+
+    /// maps a position in egui's space onto the physical back buffer.
+    pub fn to_physical(self, pos: Pos2, w: f32, h: f32) -> Pos2 {
+        match self {
+            Orientation::Deg0 => pos,
+            Orientation::Deg90 => Pos2::new(w - pos.y, pos.x),
+            Orientation::Deg180 => Pos2::new(w - pos.x, h - pos.y),
+            Orientation::Deg270 => Pos2::new(pos.y, h - pos.x),
+        }
+    }
+
+    /// rotates the clip space output of a projection matrix.
+    fn rotate_projection(self, mat: Matrix4x4) -> Matrix4x4 {
+        let x = [mat.M11, mat.M21, mat.M31, mat.M41];
+        let y = [mat.M12, mat.M22, mat.M32, mat.M42];
+        let neg = |col: [f32; 4]| col.map(|v| -v);
+
+        let (x, y) = match self {
+            Orientation::Deg0 => (x, y),
+            Orientation::Deg90 => (y, neg(x)),
+            Orientation::Deg180 => (neg(x), neg(y)),
+            Orientation::Deg270 => (neg(y), x),
+        };
+
+        Matrix4x4 {
+            M11: x[0],
+            M21: x[1],
+            M31: x[2],
+            M41: x[3],
+            M12: y[0],
+            M22: y[1],
+            M32: y[2],
+            M42: y[3],
+            ..mat
+        }
+    }
+}
+
+/// which back buffer we draw into.
+/// the default is the first back buffer of the implicit swap chain.
+#[derive(Clone, Copy, Default)]
+pub struct BackBufferTarget {
+    pub swap_chain: u32,
+    pub index: u32,
+    /// `MONO`, or `LEFT`/`RIGHT` for stereo setups where each eye is its own buffer.
+    pub kind: D3DBACKBUFFER_TYPE,
+}
+
+impl BackBufferTarget {
+    unsafe fn get(&self, dev: &IDirect3DDevice9) -> windows::core::Result<IDirect3DSurface9> {
+        dev.GetBackBuffer(self.swap_chain, self.index, self.kind)
+    }
+
+    /// width and height of the back buffer in pixels.
+    pub fn size(&self, dev: &IDirect3DDevice9) -> windows::core::Result<(u32, u32)> {
+        unsafe {
+            let mut desc = D3DSURFACE_DESC::default();
+            self.get(dev)?.GetDesc(&mut desc)?;
+
+            Ok((desc.Width, desc.Height))
+        }
+    }
+
+    /// copies what's in the back buffer right now into cpu memory, as an opaque image.
+    /// fails for anything but 32-bit RGB back buffers.
+    pub fn read_pixels(&self, dev: &IDirect3DDevice9) -> windows::core::Result<ColorImage> {
+        unsafe {
+            let backbuffer = self.get(dev)?;
+
+            let mut desc = D3DSURFACE_DESC::default();
+            backbuffer.GetDesc(&mut desc)?;
+
+            if desc.Format != D3DFMT_X8R8G8B8 && desc.Format != D3DFMT_A8R8G8B8 {
+                return Err(E_FAIL.into());
+            }
+
+            // GetRenderTargetData can't read multisampled surfaces, resolve those into a plain one first.
+            let source = if desc.MultiSampleType != D3DMULTISAMPLE_NONE {
+                let mut surface: Option<IDirect3DSurface9> = None;
+
+                dev.CreateRenderTarget(
+                    desc.Width,
+                    desc.Height,
+                    desc.Format,
+                    D3DMULTISAMPLE_NONE,
+                    0,
+                    false,
+                    &mut surface,
+                    std::ptr::null_mut(),
+                )?;
+
+                let surface = surface.ok_or_else(|| windows::core::Error::from(E_FAIL))?;
+
+                dev.StretchRect(
+                    &backbuffer,
+                    std::ptr::null(),
+                    &surface,
+                    std::ptr::null(),
+                    D3DTEXF_NONE,
+                )?;
+
+                surface
+            } else {
+                backbuffer
+            };
+
+            // the back buffer lives in video memory and can't be locked, copy it somewhere that can.
+            let mut readback: Option<IDirect3DSurface9> = None;
+
+            dev.CreateOffscreenPlainSurface(
+                desc.Width,
+                desc.Height,
+                desc.Format,
+                D3DPOOL_SYSTEMMEM,
+                &mut readback,
+                std::ptr::null_mut(),
+            )?;
+
+            let readback = readback.ok_or_else(|| windows::core::Error::from(E_FAIL))?;
+
+            dev.GetRenderTargetData(&source, &readback)?;
+
+            let mut locked_rect = D3DLOCKED_RECT::default();
+            readback.LockRect(&mut locked_rect, std::ptr::null(), D3DLOCK_READONLY as _)?;
+
+            let (w, h) = (desc.Width as usize, desc.Height as usize);
+
+            // B, G, R, X in memory. rows can be padded, so go by the pitch rather than the width.
+            let pixels = (0..h)
+                .flat_map(|y| {
+                    let row = (locked_rect.pBits as *const u8).add(y * locked_rect.Pitch as usize);
+                    std::slice::from_raw_parts(row as *const [u8; 4], w)
+                })
+                .map(|&[b, g, r, _]| Color32::from_rgb(r, g, b))
+                .collect();
+
+            readback.UnlockRect()?;
+
+            Ok(ColorImage {
+                size: [w, h],
+                pixels,
+            })
+        }
+    }
+}
+
+/// where in the game's frame `present` is called from.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RenderStage {
+    /// from a `Present` hook, after the scene ended. we open our own scene around our draws,
+    /// and leave the back buffer bound as the render target afterwards.
+    #[default]
+    Present,
+    /// from an `EndScene` hook, inside the game's scene. we draw within it,
+    /// and bind whatever render target the game had bound again afterwards, which may not be the back buffer.
+    EndScene,
+}
+
+/// knobs that change how `setup_state` configures the device.
+#[derive(Clone, Copy)]
+pub struct RenderOptions {
+    pub orientation: Orientation,
+    /// mirrors egui vertically, for hosts where the overlay ends up upside down.
+    pub flip_y: bool,
+    /// the back buffer expects linear colors, see `EguiDx9::set_srgb_framebuffer`.
+    pub srgb_framebuffer: bool,
+    pub back_buffer: BackBufferTarget,
+    pub stage: RenderStage,
+    /// shift by half a pixel to line texels up with pixels, as d3d9 samples at pixel corners.
+    pub half_pixel_offset: bool,
+    /// unbind the game's depth-stencil surface while we draw, see `EguiDx9::set_detach_depth_stencil`.
+    pub detach_depth_stencil: bool,
+    /// vertex color byte order. the fixed-function pipeline wants BGRA.
+    pub color_order: ColorOrder,
+    /// back buffer pixels per egui point. egui points are client pixels,
+    /// so this is above 1 when the game renders at a higher resolution than its window.
+    pub pixels_per_point: f32,
+    /// clip to egui's clip rects, see `EguiDx9::set_scissor_enabled`.
+    pub scissor: bool,
+    /// world, view and projection to draw with instead of our screen-space ones,
+    /// see `EguiDx9::set_transform_override`.
+    pub transform: Option<(Matrix4x4, Matrix4x4, Matrix4x4)>,
+    /// clip on the cpu instead of with scissor rects, see `EguiDx9::set_cpu_clipping`.
+    pub cpu_clip: bool,
+    /// skip meshes that are entirely transparent, see `EguiDx9::set_cull_transparent`.
+    pub cull_transparent: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            orientation: Orientation::default(),
+            flip_y: false,
+            srgb_framebuffer: false,
+            back_buffer: BackBufferTarget::default(),
+            stage: RenderStage::default(),
+            half_pixel_offset: true,
+            detach_depth_stencil: false,
+            color_order: ColorOrder::Bgra,
+            pixels_per_point: 1.,
+            scissor: true,
+            transform: None,
+            cpu_clip: false,
+            cull_transparent: false,
+        }
+    }
+}
+
+impl RenderOptions {
+    /// whether to clip with scissor rects, instead of not at all or on the cpu.
+    /// clip rects are in screen space, which means nothing once the UI is somewhere in the world.
+    pub fn hardware_scissor(&self) -> bool {
+        self.scissor && !self.cpu_clip && self.transform.is_none()
+    }
+
+    /// maps a point in egui's (logical) space to where it ends up in client space.
+    pub fn physical_pos(&self, mut pos: Pos2, w: f32, h: f32) -> Pos2 {
+        if self.flip_y {
+            let (_, logical_h) = self.orientation.logical_size(w, h);
+            pos.y = logical_h - pos.y;
+        }
+
+        self.orientation.to_physical(pos, w, h)
+    }
+
+    /// scissor rects are applied on the back buffer, so they have to follow the projection.
+    pub fn physical_clip(&self, clip: Rect, w: f32, h: f32) -> Rect {
+        let clip = if self.orientation == Orientation::Deg0 && !self.flip_y {
+            clip
+        } else {
+            Rect::from_two_pos(
+                self.physical_pos(clip.min, w, h),
+                self.physical_pos(clip.max, w, h),
+            )
+        };
+
+        let ppp = self.pixels_per_point;
+
+        Rect::from_min_max(
+            Pos2::new(clip.min.x * ppp, clip.min.y * ppp),
+            Pos2::new(clip.max.x * ppp, clip.max.y * ppp),
+        )
+    }
+}
 
 pub struct DxState {
     original_state: IDirect3DStateBlock9,
     original_world: Matrix4x4,
     original_view: Matrix4x4,
     original_proj: Matrix4x4,
+    original_decl: Option<IDirect3DVertexDeclaration9>,
+    original_fvf: u32,
     backbuffer: IDirect3DSurface9,
+    depth_stencil: Option<IDirect3DSurface9>,
+    intermediate: bool,
+    began_scene: bool,
+    /// the surface we draw into, fetched once so the intermediate copy goes back to the same one.
+    target: IDirect3DSurface9,
     dev: IDirect3DDevice9,
 }
 
 impl DxState {
-    pub fn setup(dev: &IDirect3DDevice9, viewport: D3DVIEWPORT9) -> Self {
+    /// draws into `surface` if given, the back buffer `opts` points at otherwise.
+    pub fn setup(
+        dev: &IDirect3DDevice9,
+        viewport: D3DVIEWPORT9,
+        opts: &RenderOptions,
+        surface: Option<&IDirect3DSurface9>,
+    ) -> Self {
         unsafe {
             // backup state
             let original_state = {
-                expect!(
+                dx_expect!(
                     dev.CreateStateBlock(D3DSBT_ALL),
                     "unable to back up game state"
                 )
             };
 
-            expect!(
+            dx_expect!(
                 original_state.Capture(),
                 "unable to capture dx state backup"
             );
@@ -50,34 +345,69 @@ impl DxState {
             let mut original_view: Matrix4x4 = Default::default();
             let mut original_proj: Matrix4x4 = Default::default();
 
-            expect!(
+            dx_expect!(
                 // https://github.com/apitrace/dxsdk/blob/d964b66467aaa734edbc24326da8119f5f063dd3/Include/d3d9types.h#L333C35-L333C56
                 dev.GetTransform(D3DTRANSFORMSTATETYPE(0 + 256), &mut original_world),
                 "unable to backup world matrix"
             );
-            expect!(
+            dx_expect!(
                 dev.GetTransform(D3DTS_VIEW, &mut original_view),
                 "unable to backup view matrix"
             );
-            expect!(
+            dx_expect!(
                 dev.GetTransform(D3DTS_PROJECTION, &mut original_proj),
                 "unable to backup projection matrix"
             );
 
-            let backbuffer = expect!(
-                dev.GetBackBuffer(0, 0, D3DBACKBUFFER_TYPE_MONO),
-                "unable to get original backbuffer"
-            );
+            // our SetFVF replaces the game's vertex declaration. state blocks are supposed to cover it,
+            // but games that mix declarations and FVFs have been known to come back broken, so be explicit.
+            // a null declaration just means none is set.
+            let original_decl = dev.GetVertexDeclaration().ok();
+            let mut original_fvf = 0;
+            dx_expect!(dev.GetFVF(&mut original_fvf), "unable to backup fvf");
+
+            let target = match surface {
+                Some(surface) => surface.clone(),
+                None => dx_expect!(opts.back_buffer.get(dev), "unable to get back buffer"),
+            };
+
+            // what we bind again once we're done.
+            let backbuffer = match opts.stage {
+                RenderStage::Present => target.clone(),
+                RenderStage::EndScene => dx_expect!(
+                    dev.GetRenderTarget(0),
+                    "unable to get original render target"
+                ),
+            };
+
+            // surface bindings aren't part of state blocks. this fails if there is none bound.
+            let depth_stencil = if opts.detach_depth_stencil {
+                dev.GetDepthStencilSurface().ok()
+            } else {
+                None
+            };
 
             // set our desired state
-            expect!(setup_state(dev, viewport), "unable to setup state");
+            let intermediate = dx_expect!(
+                setup_state(dev, &target, viewport, opts),
+                "unable to setup state"
+            );
+
+            // drawing is only allowed within a scene. if the game is still in one anyway, just use that.
+            let began_scene = opts.stage == RenderStage::Present && dev.BeginScene().is_ok();
 
             Self {
                 original_state,
                 original_world,
                 original_view,
                 original_proj,
+                original_decl,
+                original_fvf,
                 backbuffer,
+                depth_stencil,
+                intermediate,
+                began_scene,
+                target,
                 dev: dev.clone(),
             }
         }
@@ -88,86 +418,120 @@ impl Drop for DxState {
     fn drop(&mut self) {
         // restore the previous state
         unsafe {
-            expect!(
+            if self.began_scene {
+                dx_expect!(self.dev.EndScene(), "unable to end scene");
+            }
+
+            dx_expect!(
                 self.dev
                     .SetTransform(D3DTRANSFORMSTATETYPE(0 + 256), &self.original_world),
                 "unable to reset world matrix"
             );
-            expect!(
+            dx_expect!(
                 self.dev.SetTransform(D3DTS_VIEW, &self.original_view),
                 "unable to reset view matrix"
             );
-            expect!(
+            dx_expect!(
                 self.dev.SetTransform(D3DTS_PROJECTION, &self.original_proj),
                 "unable to reset projection matrix"
             );
 
-            let backbuffer = expect!(
-                self.dev.GetBackBuffer(0, 0, D3DBACKBUFFER_TYPE_MONO),
-                "unable to get back buffer"
-            );
-
-            let render_target = expect!(self.dev.GetRenderTarget(0), "unable to get render target");
-
-            expect!(
-                self.dev.StretchRect(
-                    &render_target,
-                    std::ptr::null(),
-                    &backbuffer,
-                    std::ptr::null(),
-                    D3DTEXF_NONE,
-                ),
-                "unable to overwrite back buffer"
-            );
+            if self.intermediate {
+                let render_target =
+                    dx_expect!(self.dev.GetRenderTarget(0), "unable to get render target");
+
+                dx_expect!(
+                    self.dev.StretchRect(
+                        &render_target,
+                        std::ptr::null(),
+                        &self.target,
+                        std::ptr::null(),
+                        D3DTEXF_NONE,
+                    ),
+                    "unable to overwrite back buffer"
+                );
+            }
 
-            expect!(
+            dx_expect!(
                 self.dev.SetRenderTarget(0, &self.backbuffer),
-                "unable to get original backbuffer"
+                "unable to restore original render target"
             );
 
-            expect!(
+            if let Some(depth_stencil) = &self.depth_stencil {
+                dx_expect!(
+                    self.dev.SetDepthStencilSurface(depth_stencil),
+                    "unable to reset depth stencil surface"
+                );
+            }
+
+            dx_expect!(
                 self.original_state.Apply(),
                 "unable to re-apply captured state"
             );
+
+            // an FVF is turned into a declaration under the hood, so restoring the declaration would lose it.
+            if self.original_fvf != 0 {
+                dx_expect!(self.dev.SetFVF(self.original_fvf), "unable to reset fvf");
+            } else if let Some(decl) = &self.original_decl {
+                dx_expect!(
+                    self.dev.SetVertexDeclaration(decl),
+                    "unable to reset vertex declaration"
+                );
+            }
         }
     }
 }
 
+/// returns whether we're drawing into an intermediate copy of the back buffer,
+/// which has to be copied back once we're done.
 fn setup_state(
     dev: &IDirect3DDevice9,
+    backbuffer: &IDirect3DSurface9,
     viewport: D3DVIEWPORT9,
-) -> Result<(), Box<dyn std::error::Error>> {
+    opts: &RenderOptions,
+) -> windows::core::Result<bool> {
     unsafe {
         // general set up
-        let backbuffer: IDirect3DSurface9 = dev.GetBackBuffer(0, 0, D3DBACKBUFFER_TYPE_MONO)?;
-
         let mut desc = D3DSURFACE_DESC::default();
         backbuffer.GetDesc(&mut desc)?;
 
-        let mut surface: Option<IDirect3DSurface9> = None;
+        // StretchRect can resolve a multisampled surface, but can't copy back into one.
+        // draw straight onto those instead of going through an intermediate.
+        let intermediate = desc.MultiSampleType == D3DMULTISAMPLE_NONE;
+
+        if intermediate {
+            let mut surface: Option<IDirect3DSurface9> = None;
 
-        dev.CreateRenderTarget(
-            desc.Width,
-            desc.Height,
-            desc.Format,
-            D3DMULTISAMPLE_TYPE(0),
-            0,
-            true,
-            &mut surface,
-            std::ptr::null_mut(),
-        )?;
+            dev.CreateRenderTarget(
+                desc.Width,
+                desc.Height,
+                desc.Format,
+                D3DMULTISAMPLE_TYPE(0),
+                0,
+                true,
+                &mut surface,
+                std::ptr::null_mut(),
+            )?;
 
-        let surface = surface.ok_or("unable to create surface")?;
+            let surface = surface.ok_or_else(|| windows::core::Error::from(E_FAIL))?;
 
-        dev.SetRenderTarget(0, &surface)?;
+            dev.SetRenderTarget(0, &surface)?;
 
-        dev.StretchRect(
-            &backbuffer,
-            std::ptr::null(),
-            &surface,
-            std::ptr::null(),
-            D3DTEXF_NONE,
-        )?;
+            dev.StretchRect(
+                backbuffer,
+                std::ptr::null(),
+                &surface,
+                std::ptr::null(),
+                D3DTEXF_NONE,
+            )?;
+        } else {
+            dev.SetRenderTarget(0, backbuffer)?;
+            dev.SetRenderState(D3DRS_MULTISAMPLEANTIALIAS, true as _)?;
+        }
+
+        if opts.detach_depth_stencil {
+            dev.SetDepthStencilSurface(None)?;
+        }
 
         dev.SetViewport(&viewport)?;
 
@@ -177,40 +541,10 @@ fn setup_state(
         dev.SetFVF(FVF_CUSTOMVERTEX)?;
 
         // set up matrix
-        let l = 0.5;
-        let r = viewport.Width as f32 + 0.5;
-        let t = 0.5;
-        let b = viewport.Height as f32 + 0.5;
-
-        let mat_ident = Matrix4x4 {
-            M11: 1.0,
-            M22: 1.0,
-            M33: 1.0,
-            M44: 1.0,
-            ..Default::default()
-        };
-
-        let mat_proj = Matrix4x4 {
-            M11: 2.0 / (r - l),
-            M12: 0.0,
-            M13: 0.0,
-            M14: 0.0,
-            M21: 0.0,
-            M22: 2.0 / (t - b),
-            M23: 0.0,
-            M24: 0.0,
-            M31: 0.0,
-            M32: 0.0,
-            M33: 0.5,
-            M34: 0.0,
-            M41: (l + r) / (l - r),
-            M42: (t + b) / (b - t),
-            M43: 0.5,
-            M44: 1.0,
-        };
+        let ((mat_world, mat_view, mat_proj), scissor) = context_transform(&viewport, opts, None);
 
-        dev.SetTransform(D3DTRANSFORMSTATETYPE(0 + 256), &mat_ident)?;
-        dev.SetTransform(D3DTS_VIEW, &mat_ident)?;
+        dev.SetTransform(D3DTRANSFORMSTATETYPE(0 + 256), &mat_world)?;
+        dev.SetTransform(D3DTS_VIEW, &mat_view)?;
         dev.SetTransform(D3DTS_PROJECTION, &mat_proj)?;
 
         // set up render state
@@ -228,7 +562,7 @@ fn setup_state(
         dev.SetRenderState(D3DRS_BLENDOPALPHA, D3DBLENDOP_ADD.0 as _)?;
         dev.SetRenderState(D3DRS_SRCBLENDALPHA, D3DBLEND_ONE.0 as _)?;
         dev.SetRenderState(D3DRS_DESTBLENDALPHA, D3DBLEND_INVSRCALPHA.0 as _)?;
-        dev.SetRenderState(D3DRS_SCISSORTESTENABLE, true as _)?;
+        dev.SetRenderState(D3DRS_SCISSORTESTENABLE, scissor as _)?;
         dev.SetRenderState(D3DRS_FOGENABLE, false as _)?;
         dev.SetRenderState(D3DRS_RANGEFOGENABLE, false as _)?;
         dev.SetRenderState(D3DRS_SPECULARENABLE, false as _)?;
@@ -237,7 +571,7 @@ fn setup_state(
         dev.SetRenderState(D3DRS_LIGHTING, false as _)?;
         dev.SetRenderState(D3DRS_TEXTUREFACTOR, 0xFFFFFFFF)?;
         dev.SetRenderState(D3DRS_COLORWRITEENABLE, 0xFFFFFFFF)?;
-        dev.SetRenderState(D3DRS_SRGBWRITEENABLE, false as _)?;
+        dev.SetRenderState(D3DRS_SRGBWRITEENABLE, opts.srgb_framebuffer as _)?;
         dev.SetRenderState(D3DRS_LASTPIXEL, true as _)?;
 
         // set up texture stages
@@ -264,7 +598,300 @@ fn setup_state(
         dev.SetSamplerState(0, D3DSAMP_ADDRESSU, D3DTADDRESS_CLAMP.0 as _)?;
         dev.SetSamplerState(0, D3DSAMP_ADDRESSV, D3DTADDRESS_CLAMP.0 as _)?;
         dev.SetSamplerState(0, D3DSAMP_ADDRESSW, D3DTADDRESS_CLAMP.0 as _)?;
+        dev.SetSamplerState(0, D3DSAMP_SRGBTEXTURE, opts.srgb_framebuffer as _)?;
+
+        Ok(intermediate)
+    }
+}
+
+fn identity() -> Matrix4x4 {
+    Matrix4x4 {
+        M11: 1.0,
+        M22: 1.0,
+        M33: 1.0,
+        M44: 1.0,
+        ..Default::default()
+    }
+}
+
+/// the world, view and projection a context is drawn with, and whether it's clipped.
+/// `custom` is the projection from `EguiDx9::add_context`, our own UI has `None`.
+/// clip rects are in screen space, so they mean nothing under transforms of the caller's.
+pub fn context_transform(
+    viewport: &D3DVIEWPORT9,
+    opts: &RenderOptions,
+    custom: Option<Matrix4x4>,
+) -> ((Matrix4x4, Matrix4x4, Matrix4x4), bool) {
+    match (custom, opts.transform) {
+        (Some(mat_proj), _) => ((identity(), identity(), mat_proj), false),
+        (None, Some(transform)) => (transform, false),
+        (None, None) => (
+            (identity(), identity(), projection(viewport, opts)),
+            opts.hardware_scissor(),
+        ),
+    }
+}
+
+/// egui's space onto the viewport, with the flip and rotation `opts` ask for.
+fn projection(viewport: &D3DVIEWPORT9, opts: &RenderOptions) -> Matrix4x4 {
+    // the ortho projection is built in egui's space, and then rotated onto the back buffer.
+    let ppp = opts.pixels_per_point;
+    let (w, h) = opts
+        .orientation
+        .logical_size(viewport.Width as f32 / ppp, viewport.Height as f32 / ppp);
+
+    // a degenerate viewport would divide by zero below.
+    let (w, h) = (w.max(1.), h.max(1.));
+    // half a back buffer pixel, in points.
+    let offset = if opts.half_pixel_offset {
+        0.5 / ppp
+    } else {
+        0.
+    };
+
+    let l = offset;
+    let r = w + offset;
+    let t = offset;
+    let b = h + offset;
+
+    let mat_proj = Matrix4x4 {
+        M11: 2.0 / (r - l),
+        M12: 0.0,
+        M13: 0.0,
+        M14: 0.0,
+        M21: 0.0,
+        M22: 2.0 / (t - b),
+        M23: 0.0,
+        M24: 0.0,
+        M31: 0.0,
+        M32: 0.0,
+        M33: 0.5,
+        M34: 0.0,
+        M41: (l + r) / (l - r),
+        M42: (t + b) / (b - t),
+        M43: 0.5,
+        M44: 1.0,
+    };
+
+    // flipping happens in egui's space, before rotating onto the back buffer.
+    let mat_proj = if opts.flip_y {
+        Matrix4x4 {
+            M22: -mat_proj.M22,
+            M42: -mat_proj.M42,
+            ..mat_proj
+        }
+    } else {
+        mat_proj
+    };
+
+    opts.orientation.rotate_projection(mat_proj)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::{pos2, Event, Vec2};
+    use windows::Win32::{Foundation::HWND, UI::WindowsAndMessaging::WM_MOUSEMOVE};
+
+    use crate::inputman::InputManager;
+
+    const ALL: [Orientation; 4] = [
+        Orientation::Deg0,
+        Orientation::Deg90,
+        Orientation::Deg180,
+        Orientation::Deg270,
+    ];
+
+    #[test]
+    fn orientations_round_trip() {
+        let (w, h) = (800., 600.);
+        let points = [
+            pos2(0., 0.),
+            pos2(w, 0.),
+            pos2(0., h),
+            pos2(w, h),
+            pos2(123., 45.),
+        ];
+
+        for orientation in ALL {
+            let (lw, lh) = orientation.logical_size(w, h);
+
+            for pos in points {
+                let logical = orientation.to_logical(pos, w, h);
+
+                assert!(
+                    (0. ..=lw).contains(&logical.x) && (0. ..=lh).contains(&logical.y),
+                    "{orientation:?} maps {pos:?} to {logical:?}, outside {lw}x{lh}"
+                );
+                assert_eq!(
+                    orientation.to_physical(logical, w, h),
+                    pos,
+                    "{orientation:?}"
+                );
+                assert_eq!(
+                    orientation.to_logical(orientation.to_physical(pos, w, h), w, h),
+                    pos,
+                    "{orientation:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rotated_top_left_click() {
+        let (w, h) = (800., 600.);
+
+        // rotated clockwise, egui's bottom left corner ends up in the physical top left.
+        assert_eq!(
+            Orientation::Deg90.to_logical(pos2(0., 0.), w, h),
+            pos2(0., w)
+        );
+        assert_eq!(
+            Orientation::Deg180.to_logical(pos2(0., 0.), w, h),
+            pos2(w, h)
+        );
+        assert_eq!(
+            Orientation::Deg270.to_logical(pos2(0., 0.), w, h),
+            pos2(h, 0.)
+        );
+        assert_eq!(
+            Orientation::Deg0.to_logical(pos2(0., 0.), w, h),
+            pos2(0., 0.)
+        );
+    }
+
+    /// where `pos` lands in clip space, the projection is affine so w stays 1.
+    fn to_clip(mat: &Matrix4x4, pos: Pos2) -> Pos2 {
+        pos2(
+            pos.x * mat.M11 + pos.y * mat.M21 + mat.M41,
+            pos.x * mat.M12 + pos.y * mat.M22 + mat.M42,
+        )
+    }
+
+    #[test]
+    fn flip_y_mirrors_clip_space_and_input() {
+        let viewport = D3DVIEWPORT9 {
+            Width: 800,
+            Height: 600,
+            MaxZ: 1.,
+            ..Default::default()
+        };
+
+        let normal = projection(&viewport, &RenderOptions::default());
+        let flipped = projection(
+            &viewport,
+            &RenderOptions {
+                flip_y: true,
+                ..Default::default()
+            },
+        );
+
+        let vertex = pos2(200., 150.);
+        let (a, b) = (to_clip(&normal, vertex), to_clip(&flipped, vertex));
+        assert_eq!(a.x, b.x);
+        assert_eq!(a.y, -b.y);
+
+        // a click where the unflipped vertex shows up reaches egui where the flipped one is.
+        let mut input = InputManager::new(HWND::default());
+        input.set_flip_y(true);
+        input.process(WM_MOUSEMOVE, 0, 150 << 16 | 200);
+
+        let events = input.collect_input(800., 600., 1., Vec2::splat(1.)).events;
+        assert_eq!(events, [Event::PointerMoved(pos2(200., 450.))]);
+
+        let clicked = to_clip(&flipped, pos2(200., 450.));
+        assert!((clicked.x - a.x).abs() < 1e-5 && (clicked.y - a.y).abs() < 1e-5);
+    }
+
+    #[test]
+    fn half_pixel_offset_toggles_and_degenerate_viewports_stay_finite() {
+        let viewport = D3DVIEWPORT9 {
+            Width: 800,
+            Height: 600,
+            MaxZ: 1.,
+            ..Default::default()
+        };
+
+        let with = projection(&viewport, &RenderOptions::default());
+        let without = projection(
+            &viewport,
+            &RenderOptions {
+                half_pixel_offset: false,
+                ..Default::default()
+            },
+        );
+
+        assert_ne!(with.M41, without.M41);
+        assert_ne!(with.M42, without.M42);
+
+        // without the offset, the viewport's corners are clip space's.
+        let corners = [
+            (to_clip(&without, pos2(0., 0.)), pos2(-1., 1.)),
+            (to_clip(&without, pos2(800., 600.)), pos2(1., -1.)),
+        ];
+        for (clip, expected) in corners {
+            assert!(
+                (clip - expected).length() < 1e-5,
+                "{clip:?} != {expected:?}"
+            );
+        }
+
+        // r == l and t == b.
+        for (width, height) in [(0, 0), (0, 600), (800, 0)] {
+            let viewport = D3DVIEWPORT9 {
+                Width: width,
+                Height: height,
+                ..viewport
+            };
+
+            for half_pixel_offset in [true, false] {
+                let mat = projection(
+                    &viewport,
+                    &RenderOptions {
+                        half_pixel_offset,
+                        ..Default::default()
+                    },
+                );
+
+                let Matrix4x4 {
+                    M11, M22, M41, M42, ..
+                } = mat;
+                assert!(
+                    [M11, M22, M41, M42].iter().all(|m| m.is_finite()),
+                    "{width}x{height} gives a non-finite projection"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn contexts_get_their_own_projection() {
+        let viewport = D3DVIEWPORT9 {
+            Width: 800,
+            Height: 600,
+            MaxZ: 1.,
+            ..Default::default()
+        };
+        let opts = RenderOptions::default();
+
+        // a screen-space menu, and world-space markers with the game's view * projection.
+        let world = Matrix4x4 {
+            M11: 0.5,
+            M22: 0.5,
+            M33: 1.,
+            M34: 1.,
+            ..Default::default()
+        };
+
+        let ((_, _, screen_proj), screen_scissor) = context_transform(&viewport, &opts, None);
+        assert_eq!(screen_proj, projection(&viewport, &opts));
+        assert!(screen_scissor);
 
-        Ok(())
+        let ((_, _, world_proj), world_scissor) = context_transform(&viewport, &opts, Some(world));
+        assert_eq!(world_proj, world);
+        assert!(
+            !world_scissor,
+            "screen-space clip rects applied in world space"
+        );
     }
 }
@@ -1,39 +1,117 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Once};
 
 use egui::{ImageData, TextureId, TexturesDelta};
 use windows::Win32::{
     Foundation::{POINT, RECT},
     Graphics::Direct3D9::{
-        IDirect3DDevice9, IDirect3DTexture9, D3DFMT_A8R8G8B8, D3DLOCKED_RECT, D3DLOCK_DISCARD,
-        D3DLOCK_READONLY, D3DPOOL_DEFAULT, D3DPOOL_SYSTEMMEM, D3DUSAGE_DYNAMIC,
+        IDirect3DDevice9, IDirect3DTexture9, D3DCAPS2_DYNAMICTEXTURES, D3DCAPS9,
+        D3DDEVICE_CREATION_PARAMETERS, D3DDISPLAYMODE, D3DFMT_A8B8G8R8, D3DFMT_A8R8G8B8, D3DFORMAT,
+        D3DLOCKED_RECT, D3DLOCK_DISCARD, D3DLOCK_READONLY, D3DPOOL_DEFAULT, D3DPOOL_MANAGED,
+        D3DPOOL_SYSTEMMEM, D3DRTYPE_TEXTURE, D3DSURFACE_DESC, D3DUSAGE_DYNAMIC,
+        D3DUSAGE_QUERY_SRGBREAD,
     },
 };
 
-#[repr(C)]
-#[derive(Clone, Copy)]
-pub struct TextureColor {
-    pub b: u8,
-    pub g: u8,
-    pub r: u8,
-    pub a: u8,
-}
+use crate::color::{ColorOrder, PackedColor};
 
 struct ManagedTexture {
     handle: Option<IDirect3DTexture9>,
-    pixels: Vec<TextureColor>,
+    /// what we recreate the texture from after a reset.
+    /// `None` for `D3DPOOL_MANAGED` textures, d3d9 keeps its own copy of those.
+    pixels: Option<Vec<PackedColor>>,
     size: [usize; 2],
 }
 
+/// creates a texture of the given size holding the given pixels, row by row.
+/// they're `0xAARRGGBB`, or `0xAABBGGRR` if the device only does `D3DFMT_A8B8G8R8`.
+/// see `EguiDx9::set_texture_creator`.
+pub type TextureCreator =
+    Box<dyn FnMut(&IDirect3DDevice9, [usize; 2], &[u32]) -> IDirect3DTexture9 + 'static>;
+
 pub struct TextureManager {
     textures: HashMap<TextureId, ManagedTexture>,
+    format: D3DFORMAT,
+    /// has to match `format`, `D3DFMT_A8R8G8B8` is BGRA and `D3DFMT_A8B8G8R8` is RGBA.
+    color_order: ColorOrder,
+    creator: Option<TextureCreator>,
+    /// create textures in `D3DPOOL_MANAGED`, see `EguiDx9::set_managed_textures`.
+    managed: bool,
+    /// the device lets us lock `D3DUSAGE_DYNAMIC` default pool textures,
+    /// so they're written directly instead of through a temporary system memory texture.
+    lockable_dynamic: bool,
+    /// images larger than this on either side are downscaled, see `EguiDx9::set_max_texture_dimension`.
+    max_dimension: Option<usize>,
+    /// textures that are sampled with sRGB decoding on or off regardless of the framebuffer,
+    /// see `EguiDx9::set_texture_srgb`. kept apart from the textures, egui may set the flag before uploading.
+    srgb: HashMap<TextureId, bool>,
+    /// the device can decode sRGB when sampling `format`.
+    srgb_read: bool,
 }
 
 impl TextureManager {
-    pub fn new() -> Self {
+    pub fn new(dev: &IDirect3DDevice9) -> Self {
+        let format = pick_format(dev);
+
+        Self {
+            lockable_dynamic: can_lock_dynamic(dev),
+            srgb_read: supports(dev, D3DUSAGE_QUERY_SRGBREAD as _, format),
+            ..Self::with_format(format)
+        }
+    }
+
+    fn with_format(format: D3DFORMAT) -> Self {
         Self {
             textures: HashMap::new(),
+            format,
+            color_order: if format == D3DFMT_A8B8G8R8 {
+                ColorOrder::Rgba
+            } else {
+                ColorOrder::Bgra
+            },
+            creator: None,
+            managed: false,
+            lockable_dynamic: false,
+            max_dimension: None,
+            srgb: HashMap::new(),
+            srgb_read: false,
         }
     }
+
+    pub fn format(&self) -> D3DFORMAT {
+        self.format
+    }
+
+    pub fn set_creator(&mut self, creator: Option<TextureCreator>) {
+        self.creator = creator;
+    }
+
+    /// `None` goes back to following the framebuffer. returns false if the device can't decode sRGB.
+    pub fn set_srgb(&mut self, tid: TextureId, srgb: Option<bool>) -> bool {
+        match srgb {
+            Some(srgb) if self.srgb_read => {
+                self.srgb.insert(tid, srgb);
+            }
+            Some(_) => return false,
+            None => {
+                self.srgb.remove(&tid);
+            }
+        }
+
+        true
+    }
+
+    /// whether `tid` is sampled with sRGB decoding, `None` if it follows the framebuffer.
+    pub fn is_srgb(&self, tid: TextureId) -> Option<bool> {
+        self.srgb.get(&tid).copied()
+    }
+
+    pub fn set_managed(&mut self, managed: bool) {
+        self.managed = managed;
+    }
+
+    pub fn set_max_dimension(&mut self, max_dimension: Option<usize>) {
+        self.max_dimension = max_dimension;
+    }
 }
 
 impl TextureManager {
@@ -66,32 +144,58 @@ impl TextureManager {
         });
     }
 
-    pub fn get_by_id(&self, id: TextureId) -> &IDirect3DTexture9 {
-        expect!(
-            &expect!(self.textures.get(&id), "unable to retrieve texture")
-                .handle
-                .as_ref(),
-            "unable to retrieve texture handle"
-        )
+    /// `None` if we don't have the texture (e.g. creating it failed) or it's deallocated for a reset.
+    pub fn get_by_id(&self, id: TextureId) -> Option<&IDirect3DTexture9> {
+        self.textures.get(&id)?.handle.as_ref()
+    }
+
+    pub fn count(&self) -> usize {
+        self.textures.len()
     }
 
+    /// roughly how much video memory our textures take, in bytes. they're all 32-bit.
+    pub fn bytes(&self) -> usize {
+        self.textures
+            .values()
+            .map(|texture| texture.size[0] * texture.size[1] * 4)
+            .sum()
+    }
+
+    /// drops every texture `keep` says no to, returns how many that were.
+    pub fn retain(&mut self, mut keep: impl FnMut(&TextureId) -> bool) -> usize {
+        let before = self.textures.len();
+        self.textures.retain(|tid, _| keep(tid));
+        self.srgb.retain(|tid, _| keep(tid));
+        before - self.textures.len()
+    }
+
+    /// `D3DPOOL_MANAGED` textures survive a reset, so only the ones we can recreate are dropped.
     pub fn deallocate_textures(&mut self) {
         self.textures.iter_mut().for_each(|(_tid, texture)| {
-            texture.handle = None;
+            if texture.pixels.is_some() {
+                texture.handle = None;
+            }
         });
     }
 
     pub fn reallocate_textures(&mut self, dev: &IDirect3DDevice9) {
+        let creator = &mut self.creator;
+        let format = self.format;
+        let lockable = self.lockable_dynamic;
+
         self.textures.iter_mut().for_each(|(_tid, texture)| {
-            let handle = new_texture_from_buffer(dev, &texture.pixels, texture.size);
+            if let Some(pixels) = &texture.pixels {
+                let handle = create_texture(creator, dev, format, pixels, texture.size, lockable);
 
-            texture.handle = Some(handle);
+                texture.handle = Some(handle);
+            }
         });
     }
 }
 
 impl TextureManager {
     fn free(&mut self, tid: &TextureId) -> bool {
+        self.srgb.remove(tid);
         self.textures.remove(tid).is_some()
     }
 
@@ -101,19 +205,63 @@ impl TextureManager {
         tid: &TextureId,
         img_data: &ImageData,
     ) {
-        let pixels = pixels_from_imagedata(img_data);
+        let pixels = pixels_from_imagedata(img_data, self.color_order);
+        let (pixels, size) = self.fit(img_data, pixels);
+
+        let texture = self.make_texture(dev, pixels, size);
+
+        self.textures.insert(*tid, texture);
+    }
+
+    /// downscales user images if either side is over `max_dimension`.
+    /// the font atlas is left alone, it's patched in place as glyphs are added.
+    fn fit(
+        &self,
+        img_data: &ImageData,
+        pixels: Vec<PackedColor>,
+    ) -> (Vec<PackedColor>, [usize; 2]) {
         let size = img_data.size();
 
-        let handle = new_texture_from_buffer(dev, &pixels, size);
+        match (img_data, self.max_dimension) {
+            (ImageData::Color(_), Some(max)) if size[0] > max || size[1] > max => {
+                downscale(&pixels, size, max)
+            }
+            _ => (pixels, size),
+        }
+    }
+
+    /// whether new textures need our own copy of their pixels to survive a reset.
+    /// the user's creator only promises `D3DPOOL_DEFAULT` textures, so it wins over `managed`.
+    fn keeps_pixels(&self) -> bool {
+        !self.managed || self.creator.is_some()
+    }
 
-        self.textures.insert(
-            *tid,
+    fn make_texture(
+        &mut self,
+        dev: &IDirect3DDevice9,
+        pixels: Vec<PackedColor>,
+        size: [usize; 2],
+    ) -> ManagedTexture {
+        if !self.keeps_pixels() {
             ManagedTexture {
-                handle: Some(handle),
-                pixels,
+                handle: Some(new_managed_texture(dev, self.format, &pixels, size)),
+                pixels: None,
                 size,
-            },
-        );
+            }
+        } else {
+            ManagedTexture {
+                handle: Some(create_texture(
+                    &mut self.creator,
+                    dev,
+                    self.format,
+                    &pixels,
+                    size,
+                    self.lockable_dynamic,
+                )),
+                pixels: Some(pixels),
+                size,
+            }
+        }
     }
 
     fn update_texture_area(
@@ -123,45 +271,42 @@ impl TextureManager {
         img_data: &ImageData,
         pos: [usize; 2],
     ) {
-        let x = pos[0];
-        let y = pos[1];
-        let w = img_data.width();
-        let h = img_data.height();
-
-        let pixels = pixels_from_imagedata(img_data);
-
-        let temp_tex = create_temporary_texture(dev, &pixels, [w, h]);
+        let size = img_data.size();
 
-        unsafe {
-            let texture = expect!(
-                self.textures.get(tid),
-                "unable to get texture to delta patch"
-            );
+        let pixels = pixels_from_imagedata(img_data, self.color_order);
+        let format = self.format;
+        let lockable = self.lockable_dynamic;
+
+        // same as drawing, a texture we don't have costs one widget rather than the host.
+        let Some(texture) = self.textures.get_mut(tid) else {
+            static WARNED: Once = Once::new();
+            WARNED.call_once(|| warn!("skipping a partial update to a missing texture"));
+            return;
+        };
+
+        // a downscaled texture has no room for a patch meant for the full size one.
+        if pos[0] + size[0] > texture.size[0] || pos[1] + size[1] > texture.size[1] {
+            static WARNED: Once = Once::new();
+            WARNED.call_once(|| warn!("skipping a partial update to a downscaled texture"));
+            return;
+        }
 
-            let src_surface = expect!(temp_tex.GetSurfaceLevel(0), "unable to get tex surface");
+        // deallocated for a reset, it's recreated from our copy. keep that current.
+        let Some(handle) = texture.handle.as_ref() else {
+            if let Some(cpu_pixels) = texture.pixels.as_mut() {
+                copy_area(cpu_pixels, texture.size[0], &pixels, size, pos);
+            }
+            return;
+        };
 
-            let dst_surface = expect!(
-                expect!(texture.handle.as_ref(), "unable to get texture handle").GetSurfaceLevel(0),
-                "unable to get tex surface"
-            );
+        match texture.pixels.as_mut() {
+            Some(cpu_pixels) => {
+                upload_area(dev, handle, format, &pixels, size, pos, lockable);
 
-            expect!(
-                dev.UpdateSurface(
-                    &src_surface,
-                    &RECT {
-                        left: 0 as _,
-                        right: w as _,
-                        top: 0 as _,
-                        bottom: h as _,
-                    },
-                    &dst_surface,
-                    &POINT {
-                        x: x as _,
-                        y: y as _,
-                    },
-                ),
-                "unable to update surface"
-            );
+                // keep our copy in sync, it's what we recreate the texture from after a reset.
+                copy_area(cpu_pixels, texture.size[0], &pixels, size, pos);
+            }
+            None => write_area(handle, &pixels, size, pos),
         }
     }
 
@@ -171,116 +316,348 @@ impl TextureManager {
         tid: &TextureId,
         img_data: &ImageData,
     ) {
-        let texture = expect!(self.textures.get_mut(tid), "unable to get texture");
-        let size = img_data.size();
+        let format = self.format;
+        let lockable = self.lockable_dynamic;
+        let pixels = pixels_from_imagedata(img_data, self.color_order);
+        let (pixels, size) = self.fit(img_data, pixels);
 
-        let pixels = pixels_from_imagedata(img_data);
+        let Some(texture) = self.textures.get_mut(tid) else {
+            static WARNED: Once = Once::new();
+            WARNED.call_once(|| warn!("skipping an update to a missing texture"));
+            return;
+        };
 
         if size != texture.size {
             // size mismatch, recreate texture
-            // free texture
-            self.free(tid);
+            // free texture, but keep its flags
+            self.textures.remove(tid);
 
             // create a new texture with new data
-            let handle = new_texture_from_buffer(dev, &pixels, size);
+            let texture = self.make_texture(dev, pixels, size);
 
             // insert new texture under same key
-            self.textures.insert(
-                *tid,
-                ManagedTexture {
-                    handle: Some(handle),
-                    pixels,
-                    size,
-                },
-            );
+            self.textures.insert(*tid, texture);
         } else {
-            // perfectly normal update operation
-            let temp_tex = create_temporary_texture(dev, &pixels, size);
-
-            unsafe {
-                expect!(
-                    expect!(texture.handle.as_ref(), "unable to get texture handle").AddDirtyRect(
-                        &RECT {
-                            left: 0,
-                            top: 0,
-                            right: size[0] as _,
-                            bottom: size[1] as _
-                        }
-                    ),
-                    "unable to dirty texture"
-                );
-
-                expect!(
-                    dev.UpdateTexture(
-                        &temp_tex,
-                        expect!(texture.handle.as_ref(), "unable to get texture handle")
-                    ),
-                    "unable to update texture"
-                );
+            let Some(handle) = texture.handle.as_ref() else {
+                // deallocated for a reset, it's recreated from our copy. keep that current.
+                if let Some(cpu_pixels) = texture.pixels.as_mut() {
+                    *cpu_pixels = pixels;
+                }
+                return;
+            };
+
+            // without a copy to diff against, managed textures are just overwritten.
+            let Some(cpu_pixels) = texture.pixels.as_mut() else {
+                write_area(handle, &pixels, size, [0, 0]);
+                return;
+            };
+
+            // egui sends the whole atlas even if only a glyph or two changed.
+            // only upload what actually differs from what's already on the gpu.
+            match dirty_region(cpu_pixels, &pixels, size[0]) {
+                // nothing changed at all.
+                None => {}
+                Some((pos, dirty_size)) if dirty_size != size => {
+                    let dirty = sub_image(&pixels, size[0], pos, dirty_size);
+
+                    upload_area(dev, handle, format, &dirty, dirty_size, pos, lockable);
+                }
+                Some(_) if lockable && is_dynamic(handle) => {
+                    write_area(handle, &pixels, size, [0, 0]);
+                }
+                // perfectly normal update operation
+                Some(_) => {
+                    let temp_tex = create_temporary_texture(dev, format, &pixels, size);
+
+                    unsafe {
+                        dx_expect!(
+                            handle.AddDirtyRect(&RECT {
+                                left: 0,
+                                top: 0,
+                                right: size[0] as _,
+                                bottom: size[1] as _
+                            }),
+                            "unable to dirty texture"
+                        );
+
+                        dx_expect!(
+                            dev.UpdateTexture(&temp_tex, handle),
+                            "unable to update texture"
+                        );
+                    }
+                }
+            }
+
+            *cpu_pixels = pixels;
+        }
+    }
+}
+
+/// copies `pixels` (`size` large) into `texture` at `pos`, through a temporary system memory texture.
+/// dynamic textures are written directly if `lockable`, textures from a `TextureCreator` may not be dynamic.
+fn upload_area(
+    dev: &IDirect3DDevice9,
+    texture: &IDirect3DTexture9,
+    format: D3DFORMAT,
+    pixels: &[PackedColor],
+    size: [usize; 2],
+    pos: [usize; 2],
+    lockable: bool,
+) {
+    if lockable && is_dynamic(texture) {
+        write_area(texture, pixels, size, pos);
+        return;
+    }
+
+    let temp_tex = create_temporary_texture(dev, format, pixels, size);
+
+    unsafe {
+        let src_surface = dx_expect!(temp_tex.GetSurfaceLevel(0), "unable to get tex surface");
+
+        let dst_surface = dx_expect!(texture.GetSurfaceLevel(0), "unable to get tex surface");
+
+        dx_expect!(
+            dev.UpdateSurface(
+                &src_surface,
+                &RECT {
+                    left: 0 as _,
+                    right: size[0] as _,
+                    top: 0 as _,
+                    bottom: size[1] as _,
+                },
+                &dst_surface,
+                &POINT {
+                    x: pos[0] as _,
+                    y: pos[1] as _,
+                },
+            ),
+            "unable to update surface"
+        );
+    }
+}
+
+/// writes `pixels` (`size` large) into `texture` at `pos` by locking it,
+/// for `D3DPOOL_MANAGED` textures and dynamic ones on devices that allow it.
+fn write_area(
+    texture: &IDirect3DTexture9,
+    pixels: &[PackedColor],
+    size: [usize; 2],
+    pos: [usize; 2],
+) {
+    unsafe {
+        let mut locked_rect = D3DLOCKED_RECT::default();
+
+        dx_expect!(
+            texture.LockRect(
+                0,
+                &mut locked_rect,
+                &RECT {
+                    left: pos[0] as _,
+                    top: pos[1] as _,
+                    right: (pos[0] + size[0]) as _,
+                    bottom: (pos[1] + size[1]) as _,
+                },
+                0
+            ),
+            "unable to lock texture"
+        );
+
+        // rows can be padded, so go by the pitch rather than the width.
+        pixels
+            .chunks_exact(size[0].max(1))
+            .enumerate()
+            .for_each(|(y, row)| {
+                let dst = (locked_rect.pBits as *mut u8).add(y * locked_rect.Pitch as usize);
+                std::ptr::copy_nonoverlapping(row.as_ptr(), dst as *mut PackedColor, row.len());
+            });
+
+        dx_expect!(texture.UnlockRect(0), "unable to unlock texture");
+    }
+}
+
+/// the smallest region where `old` and `new` differ, as position and size. `None` if they're equal.
+fn dirty_region(
+    old: &[PackedColor],
+    new: &[PackedColor],
+    width: usize,
+) -> Option<([usize; 2], [usize; 2])> {
+    if width == 0 {
+        return None;
+    }
+
+    let mut min = [usize::MAX; 2];
+    let mut max = [0; 2];
+
+    old.chunks_exact(width)
+        .zip(new.chunks_exact(width))
+        .enumerate()
+        .for_each(|(y, (old_row, new_row))| {
+            let differs = |(a, b): (&PackedColor, &PackedColor)| a != b;
+
+            if let Some(first) = old_row.iter().zip(new_row).position(differs) {
+                let last = old_row
+                    .iter()
+                    .zip(new_row)
+                    .rposition(differs)
+                    .unwrap_or(first);
+
+                min = [min[0].min(first), min[1].min(y)];
+                max = [max[0].max(last), max[1].max(y)];
             }
+        });
+
+    (min[1] != usize::MAX).then(|| (min, [max[0] - min[0] + 1, max[1] - min[1] + 1]))
+}
+
+/// cuts the `size` large region at `pos` out of an image `width` pixels wide.
+fn sub_image(
+    pixels: &[PackedColor],
+    width: usize,
+    pos: [usize; 2],
+    size: [usize; 2],
+) -> Vec<PackedColor> {
+    (pos[1]..pos[1] + size[1])
+        .flat_map(|y| &pixels[y * width + pos[0]..y * width + pos[0] + size[0]])
+        .copied()
+        .collect()
+}
+
+/// pastes the `size` large `src` into an image `width` pixels wide at `pos`.
+fn copy_area(
+    dst: &mut [PackedColor],
+    width: usize,
+    src: &[PackedColor],
+    size: [usize; 2],
+    pos: [usize; 2],
+) {
+    src.chunks_exact(size[0].max(1))
+        .enumerate()
+        .for_each(|(y, row)| {
+            let start = (pos[1] + y) * width + pos[0];
+            dst[start..start + row.len()].copy_from_slice(row);
+        });
+}
 
-            texture.pixels = pixels;
+/// shrinks an image by a whole factor until neither side is over `max`, averaging each block of pixels.
+/// every byte is averaged on its own, so this works for either `ColorOrder`.
+/// egui's colors are premultiplied, which is what makes averaging alpha with them correct.
+fn downscale(
+    pixels: &[PackedColor],
+    size: [usize; 2],
+    max: usize,
+) -> (Vec<PackedColor>, [usize; 2]) {
+    let factor = size[0].max(size[1]).div_ceil(max);
+    let new_size = [size[0].div_ceil(factor), size[1].div_ceil(factor)];
+
+    let mut scaled = Vec::with_capacity(new_size[0] * new_size[1]);
+
+    for y in 0..new_size[1] {
+        for x in 0..new_size[0] {
+            // blocks on the right and bottom edges may be cut short.
+            let rows = y * factor..((y + 1) * factor).min(size[1]);
+            let cols = x * factor..((x + 1) * factor).min(size[0]);
+
+            let mut sum = [0u32; 4];
+            let mut count = 0;
+
+            for row in rows {
+                for pixel in &pixels[row * size[0] + cols.start..row * size[0] + cols.end] {
+                    pixel
+                        .0
+                        .to_le_bytes()
+                        .iter()
+                        .zip(sum.iter_mut())
+                        .for_each(|(byte, sum)| *sum += *byte as u32);
+                    count += 1;
+                }
+            }
+
+            scaled.push(PackedColor(u32::from_le_bytes(
+                sum.map(|sum| ((sum + count / 2) / count) as u8),
+            )));
         }
     }
+
+    (scaled, new_size)
 }
 
-fn pixels_from_imagedata(img_data: &ImageData) -> Vec<TextureColor> {
+fn pixels_from_imagedata(img_data: &ImageData, order: ColorOrder) -> Vec<PackedColor> {
     match img_data {
         ImageData::Font(f) => f
             .srgba_pixels(None)
-            .map(|c| {
-                let cols = c.to_array();
-                TextureColor {
-                    r: cols[0],
-                    g: cols[1],
-                    b: cols[2],
-                    a: cols[3],
-                }
-            })
-            .collect(),
-        ImageData::Color(x) => x
-            .pixels
-            .iter()
-            .map(|c| {
-                let cols = c.to_array();
-                TextureColor {
-                    r: cols[0],
-                    g: cols[1],
-                    b: cols[2],
-                    a: cols[3],
-                }
-            })
+            .map(|c| order.pack(c.to_array()))
             .collect(),
+        ImageData::Color(x) => x.pixels.iter().map(|c| order.pack(c.to_array())).collect(),
+    }
+}
+
+/// `D3DFMT_A8R8G8B8` if the device can sample it, `D3DFMT_A8B8G8R8` if only that one works.
+/// every device we've seen does the former, so that's also what we go with if we can't tell.
+fn pick_format(dev: &IDirect3DDevice9) -> D3DFORMAT {
+    choose_format(|format| supports(dev, D3DUSAGE_DYNAMIC as _, format))
+}
+
+fn choose_format(supported: impl Fn(D3DFORMAT) -> bool) -> D3DFORMAT {
+    if !supported(D3DFMT_A8R8G8B8) && supported(D3DFMT_A8B8G8R8) {
+        D3DFMT_A8B8G8R8
+    } else {
+        D3DFMT_A8R8G8B8
+    }
+}
+
+/// whether textures of `format` can be created with (or, for `D3DUSAGE_QUERY_*`, do) `usage`.
+fn supports(dev: &IDirect3DDevice9, usage: u32, format: D3DFORMAT) -> bool {
+    unsafe {
+        let Ok(d3d) = dev.GetDirect3D() else {
+            return false;
+        };
+
+        let mut params = D3DDEVICE_CREATION_PARAMETERS::default();
+        let mut mode = D3DDISPLAYMODE::default();
+
+        dev.GetCreationParameters(&mut params).is_ok()
+            && dev.GetDisplayMode(0, &mut mode).is_ok()
+            && d3d
+                .CheckDeviceFormat(
+                    params.AdapterOrdinal,
+                    params.DeviceType,
+                    mode.Format,
+                    usage,
+                    D3DRTYPE_TEXTURE,
+                    format,
+                )
+                .is_ok()
     }
 }
 
 fn create_temporary_texture(
     dev: &IDirect3DDevice9,
-    buf: &[TextureColor],
+    format: D3DFORMAT,
+    buf: &[PackedColor],
     size: [usize; 2],
 ) -> IDirect3DTexture9 {
     unsafe {
         let mut temp_texture: Option<IDirect3DTexture9> = None;
 
-        expect!(
+        dx_expect!(
             dev.CreateTexture(
                 size[0] as _,
                 size[1] as _,
                 1,
                 D3DUSAGE_DYNAMIC as _,
-                D3DFMT_A8R8G8B8,
+                format,
                 D3DPOOL_SYSTEMMEM,
                 &mut temp_texture,
                 std::ptr::null_mut()
             ),
-            "unable to create temporary texture"
+            format!("unable to create {}x{} temporary texture", size[0], size[1])
         );
 
         let temp_texture = expect!(temp_texture, "unable to create temporary texture");
 
         let mut locked_rect = D3DLOCKED_RECT::default();
 
-        expect!(
+        dx_expect!(
             temp_texture.LockRect(
                 0,
                 &mut locked_rect,
@@ -290,10 +667,10 @@ fn create_temporary_texture(
             "unable to lock temporary texture"
         );
 
-        std::slice::from_raw_parts_mut(locked_rect.pBits as *mut TextureColor, size[0] * size[1])
+        std::slice::from_raw_parts_mut(locked_rect.pBits as *mut PackedColor, size[0] * size[1])
             .copy_from_slice(buf);
 
-        expect!(
+        dx_expect!(
             temp_texture.UnlockRect(0),
             "unable to unlock temporary texture"
         );
@@ -302,36 +679,262 @@ fn create_temporary_texture(
     }
 }
 
+/// goes through the user's `TextureCreator` if there is one, `new_texture_from_buffer` otherwise.
+fn create_texture(
+    creator: &mut Option<TextureCreator>,
+    dev: &IDirect3DDevice9,
+    format: D3DFORMAT,
+    buf: &[PackedColor],
+    size: [usize; 2],
+    lockable: bool,
+) -> IDirect3DTexture9 {
+    match creator {
+        Some(creator) => {
+            // `PackedColor` is a transparent u32, already in the order `format` wants.
+            let pixels =
+                unsafe { std::slice::from_raw_parts(buf.as_ptr() as *const u32, buf.len()) };
+
+            creator(dev, size, pixels)
+        }
+        None => new_texture_from_buffer(dev, format, buf, size, lockable),
+    }
+}
+
+/// a `D3DPOOL_MANAGED` texture holding `buf`, written directly instead of through a temporary.
+fn new_managed_texture(
+    dev: &IDirect3DDevice9,
+    format: D3DFORMAT,
+    buf: &[PackedColor],
+    size: [usize; 2],
+) -> IDirect3DTexture9 {
+    let mut texture: Option<IDirect3DTexture9> = None;
+
+    unsafe {
+        dx_expect!(
+            dev.CreateTexture(
+                size[0] as _,
+                size[1] as _,
+                1,
+                0,
+                format,
+                D3DPOOL_MANAGED,
+                &mut texture,
+                std::ptr::null_mut(),
+            ),
+            format!("unable to create {}x{} managed texture", size[0], size[1])
+        );
+    }
+
+    let texture = expect!(texture, "unable to create managed texture");
+
+    write_area(&texture, buf, size, [0, 0]);
+
+    texture
+}
+
+/// a dynamic `D3DPOOL_DEFAULT` texture holding `buf`. if `lockable`, it's written directly,
+/// otherwise through a temporary system memory texture and `UpdateTexture`.
 fn new_texture_from_buffer(
     dev: &IDirect3DDevice9,
-    buf: &[TextureColor],
+    format: D3DFORMAT,
+    buf: &[PackedColor],
     size: [usize; 2],
+    lockable: bool,
 ) -> IDirect3DTexture9 {
-    let temp_tex = create_temporary_texture(dev, buf, size);
     let mut texture: Option<IDirect3DTexture9> = None;
 
     unsafe {
-        expect!(
+        dx_expect!(
             dev.CreateTexture(
                 size[0] as _,
                 size[1] as _,
                 1,
                 D3DUSAGE_DYNAMIC as _,
-                D3DFMT_A8R8G8B8,
+                format,
                 D3DPOOL_DEFAULT,
                 &mut texture,
                 std::ptr::null_mut(),
             ),
-            "unable to create texture"
+            format!("unable to create {}x{} texture", size[0], size[1])
         );
 
         let texture = expect!(texture, "unable to create texture");
 
-        expect!(
-            dev.UpdateTexture(&temp_tex, &texture),
-            "unable to upload texture"
-        );
+        if lockable {
+            write_area(&texture, buf, size, [0, 0]);
+        } else {
+            let temp_tex = create_temporary_texture(dev, format, buf, size);
+
+            dx_expect!(
+                dev.UpdateTexture(&temp_tex, &texture),
+                "unable to upload texture"
+            );
+        }
 
         texture
     }
 }
+
+/// whether the device lets us lock `D3DUSAGE_DYNAMIC` textures in `D3DPOOL_DEFAULT`.
+fn can_lock_dynamic(dev: &IDirect3DDevice9) -> bool {
+    let mut caps = D3DCAPS9::default();
+
+    unsafe { dev.GetDeviceCaps(&mut caps) }.is_ok()
+        && caps.Caps2 & D3DCAPS2_DYNAMICTEXTURES as u32 != 0
+}
+
+/// ours always are, ones from a `TextureCreator` might not be.
+fn is_dynamic(texture: &IDirect3DTexture9) -> bool {
+    let mut desc = D3DSURFACE_DESC::default();
+
+    unsafe { texture.GetLevelDesc(0, &mut desc) }.is_ok()
+        && desc.Pool == D3DPOOL_DEFAULT
+        && desc.Usage & D3DUSAGE_DYNAMIC as u32 != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(size: [usize; 2]) -> Vec<PackedColor> {
+        (0..size[0] * size[1])
+            .map(|i| PackedColor(i as u32))
+            .collect()
+    }
+
+    #[test]
+    fn dirty_region_bounds_the_changes() {
+        let old = image([16, 8]);
+
+        assert_eq!(dirty_region(&old, &old, 16), None);
+
+        let mut new = old.clone();
+        new[3 * 16 + 5].0 ^= 0xFF;
+        assert_eq!(dirty_region(&old, &new, 16), Some(([5, 3], [1, 1])));
+
+        // two changes far apart make one box around both.
+        new[6 * 16 + 1].0 ^= 0xFF;
+        assert_eq!(dirty_region(&old, &new, 16), Some(([1, 3], [5, 4])));
+
+        let whole = image([16, 8])
+            .iter()
+            .map(|c| PackedColor(!c.0))
+            .collect::<Vec<_>>();
+        assert_eq!(dirty_region(&old, &whole, 16), Some(([0, 0], [16, 8])));
+    }
+
+    #[test]
+    fn textures_egui_forgot_are_collected() {
+        let mut tex_man = TextureManager::with_format(D3DFMT_A8R8G8B8);
+
+        for tid in [
+            TextureId::Managed(0),
+            TextureId::Managed(1),
+            TextureId::User(2),
+        ] {
+            let texture = ManagedTexture {
+                handle: None,
+                pixels: Some(image([4, 4])),
+                size: [4, 4],
+            };
+            tex_man.textures.insert(tid, texture);
+        }
+
+        // only the font atlas is still alive as far as egui is concerned.
+        assert_eq!(tex_man.retain(|tid| *tid == TextureId::Managed(0)), 2);
+        assert_eq!(tex_man.count(), 1);
+        assert!(tex_man.textures.contains_key(&TextureId::Managed(0)));
+
+        assert_eq!(tex_man.retain(|_| true), 0);
+        assert_eq!(tex_man.count(), 1);
+    }
+
+    #[test]
+    fn falls_back_to_rgba_textures() {
+        assert_eq!(choose_format(|_| true), D3DFMT_A8R8G8B8);
+        assert_eq!(choose_format(|_| false), D3DFMT_A8R8G8B8);
+        assert_eq!(
+            choose_format(|format| format == D3DFMT_A8B8G8R8),
+            D3DFMT_A8B8G8R8
+        );
+
+        // the pixels have to be packed to match.
+        let bgra = TextureManager::with_format(D3DFMT_A8R8G8B8);
+        assert_eq!(bgra.color_order, ColorOrder::Bgra);
+
+        let rgba = TextureManager::with_format(D3DFMT_A8B8G8R8);
+        assert_eq!(rgba.color_order, ColorOrder::Rgba);
+    }
+
+    #[test]
+    fn managed_textures_skip_the_cpu_copy() {
+        let mut tex_man = TextureManager::with_format(D3DFMT_A8R8G8B8);
+        assert!(tex_man.keeps_pixels());
+
+        tex_man.set_managed(true);
+        assert!(!tex_man.keeps_pixels());
+
+        // a creator's textures are in `D3DPOOL_DEFAULT`, they have to be recreated after a reset.
+        tex_man.set_creator(Some(Box::new(|_, _, _| unreachable!())));
+        assert!(tex_man.keeps_pixels());
+    }
+
+    #[test]
+    fn downscale_fits_and_averages() {
+        // 2x: each 2x2 block becomes one pixel, every byte averaged on its own.
+        let bytes = |c: [u8; 4]| PackedColor(u32::from_le_bytes(c));
+        let pixels = [
+            [0, 0, 0, 0],
+            [2, 4, 6, 8],
+            [10, 10, 10, 10],
+            [10, 10, 10, 10],
+            [4, 8, 12, 16],
+            [2, 4, 6, 8],
+            [10, 10, 10, 10],
+            [10, 10, 10, 255],
+        ]
+        .map(bytes);
+
+        let (scaled, size) = downscale(&pixels, [4, 2], 2);
+        assert_eq!(size, [2, 1]);
+        assert_eq!(scaled, [bytes([2, 4, 6, 8]), bytes([10, 10, 10, 71])]);
+
+        // whatever the size, the result fits and keeps its aspect ratio.
+        for (size, max) in [([8192, 4096], 2048), ([1000, 300], 256), ([300, 1000], 256)] {
+            let (scaled, new_size) = downscale(&image(size), size, max);
+
+            assert!(new_size[0] <= max && new_size[1] <= max, "{new_size:?}");
+            assert_eq!(scaled.len(), new_size[0] * new_size[1]);
+
+            let ratio = |size: [usize; 2]| size[0] as f32 / size[1] as f32;
+            assert!(
+                (ratio(new_size) - ratio(size)).abs() < 0.05,
+                "{size:?} to {new_size:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn srgb_sampling_needs_the_device_to_decode() {
+        let tid = TextureId::User(1);
+
+        // `new` leaves this off unless `CheckDeviceFormat` allows `D3DUSAGE_QUERY_SRGBREAD`.
+        let mut tex_man = TextureManager::with_format(D3DFMT_A8R8G8B8);
+        assert!(!tex_man.set_srgb(tid, Some(true)));
+        assert_eq!(tex_man.is_srgb(tid), None);
+
+        tex_man.srgb_read = true;
+        assert!(tex_man.set_srgb(tid, Some(true)));
+        assert_eq!(tex_man.is_srgb(tid), Some(true));
+        // everything else, the font atlas included, keeps following the framebuffer.
+        assert_eq!(tex_man.is_srgb(TextureId::Managed(0)), None);
+
+        assert!(tex_man.set_srgb(tid, None));
+        assert_eq!(tex_man.is_srgb(tid), None);
+
+        // the flag is set before the upload, and goes once egui forgets the texture.
+        tex_man.set_srgb(tid, Some(false));
+        tex_man.retain(|_| false);
+        assert_eq!(tex_man.is_srgb(tid), None);
+    }
+}
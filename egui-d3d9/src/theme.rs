@@ -0,0 +1,34 @@
+use windows::{
+    core::{w, PCWSTR},
+    Win32::System::Registry::{RegGetValueW, HKEY_CURRENT_USER, RRF_RT_REG_DWORD},
+};
+
+/// whether windows is set to dark mode for apps, `None` on versions that don't have one.
+pub fn system_dark_mode() -> Option<bool> {
+    let mut light = 0u32;
+    let mut size = std::mem::size_of::<u32>() as u32;
+
+    let result = unsafe {
+        RegGetValueW(
+            HKEY_CURRENT_USER,
+            w!("Software\\Microsoft\\Windows\\CurrentVersion\\Themes\\Personalize"),
+            w!("AppsUseLightTheme"),
+            RRF_RT_REG_DWORD,
+            None,
+            Some(&mut light as *mut u32 as _),
+            Some(&mut size),
+        )
+    };
+
+    result.is_ok().then_some(light == 0)
+}
+
+/// whether a `WM_SETTINGCHANGE` is about the light/dark switch.
+pub fn is_theme_change(lparam: isize) -> bool {
+    if lparam == 0 {
+        return false;
+    }
+
+    // the name of the setting area that changed.
+    unsafe { PCWSTR(lparam as _).to_string() }.is_ok_and(|area| area == "ImmersiveColorSet")
+}
@@ -0,0 +1,96 @@
+use egui::Pos2;
+use windows::Win32::{
+    Foundation::{HWND, POINT},
+    Graphics::Gdi::ScreenToClient,
+    UI::{
+        Input::Pointer::{
+            GetPointerPenInfo, GetPointerTouchInfo, GetPointerType, POINTER_FLAG_CANCELED,
+            POINTER_FLAG_INCONTACT, POINTER_FLAG_PRIMARY, POINTER_INFO, POINTER_PEN_INFO,
+            POINTER_TOUCH_INFO,
+        },
+        WindowsAndMessaging::{
+            GetMessageExtraInfo, PEN_MASK_PRESSURE, POINTER_INPUT_TYPE, PT_PEN, PT_TOUCH,
+            TOUCH_MASK_PRESSURE,
+        },
+    },
+};
+
+/// one finger or pen, as reported by a `WM_POINTER*` message.
+pub struct TouchPoint {
+    pub id: u64,
+    pub device: u64,
+    /// client coordinates.
+    pub pos: Pos2,
+    /// `0.0..=1.0`, 0 if the device doesn't report pressure.
+    pub force: f32,
+    /// the first finger down, the one that gets to move egui's pointer. pens always are.
+    pub primary: bool,
+    pub canceled: bool,
+    pub pen: bool,
+    /// fingers always are, a pen can also hover above the screen.
+    pub in_contact: bool,
+}
+
+/// the finger or pen behind a `WM_POINTER*` message, or `None` if it's a mouse or touchpad.
+pub fn touch_point(hwnd: HWND, wparam: usize) -> Option<TouchPoint> {
+    // the low word of wparam is the pointer id.
+    let id = (wparam & 0xFFFF) as u32;
+
+    unsafe {
+        let mut kind = POINTER_INPUT_TYPE::default();
+        GetPointerType(id, &mut kind).ok()?;
+
+        // pressure is 0 to 1024 for both.
+        let (info, pressure) = if kind == PT_TOUCH {
+            let mut info = POINTER_TOUCH_INFO::default();
+            GetPointerTouchInfo(id, &mut info).ok()?;
+
+            let pressure = (info.touchMask & TOUCH_MASK_PRESSURE != 0).then_some(info.pressure);
+            (info.pointerInfo, pressure)
+        } else if kind == PT_PEN {
+            let mut info = POINTER_PEN_INFO::default();
+            GetPointerPenInfo(id, &mut info).ok()?;
+
+            let pressure = (info.penMask & PEN_MASK_PRESSURE != 0).then_some(info.pressure);
+            (info.pointerInfo, pressure)
+        } else {
+            return None;
+        };
+
+        let flags = info.pointerFlags.0;
+        let pen = kind == PT_PEN;
+
+        Some(TouchPoint {
+            id: id as _,
+            device: info.sourceDevice.0 as _,
+            pos: client_pos(hwnd, &info),
+            force: pressure.map_or(0., |pressure| (pressure as f32 / 1024.).clamp(0., 1.)),
+            primary: pen || flags & POINTER_FLAG_PRIMARY.0 != 0,
+            canceled: flags & POINTER_FLAG_CANCELED.0 != 0,
+            pen,
+            in_contact: flags & POINTER_FLAG_INCONTACT.0 != 0,
+        })
+    }
+}
+
+/// whether the mouse message being processed was made up by windows from a touch or pen we already handled.
+/// that happens when `WM_POINTER*` ends up in `DefWindowProc`.
+pub fn is_promoted_touch() -> bool {
+    // MI_WP_SIGNATURE. the bit after it tells touch and pen apart, we want both.
+    // it only uses the low 32 bits.
+    const SIGNATURE: u32 = 0xFF515700;
+    const SIGNATURE_MASK: u32 = 0xFFFFFF00;
+
+    (unsafe { GetMessageExtraInfo() }.0 as u32 & SIGNATURE_MASK) == SIGNATURE
+}
+
+/// pointer positions are in screen coordinates.
+fn client_pos(hwnd: HWND, info: &POINTER_INFO) -> Pos2 {
+    let mut point: POINT = info.ptPixelLocation;
+
+    unsafe {
+        ScreenToClient(hwnd, &mut point);
+    }
+
+    Pos2::new(point.x as _, point.y as _)
+}
@@ -98,7 +98,13 @@ fn hk_reset(
     unsafe {
         APP.as_mut().unwrap().pre_reset();
 
-        ResetHook.call(dev, presentation_parameters)
+        let result = ResetHook.call(dev.clone(), presentation_parameters);
+
+        if result.is_ok() {
+            APP.as_mut().unwrap().post_reset(&dev);
+        }
+
+        result
     }
 }
 